@@ -0,0 +1,108 @@
+use crate::user_actions::intents::IntentId;
+
+use super::common_types::{Nonce, NonceLane, PaymentsVec};
+
+multiversx_sc::imports!();
+multiversx_sc::derive_imports!();
+
+pub type OutcomeId = u64;
+
+/// Older records are overwritten once a user has more than this many
+pub const MAX_USER_OUTCOMES: OutcomeId = 50;
+
+/// Identifies an async action within the transaction that dispatched it
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode, Clone)]
+pub struct ActionRef<M: ManagedTypeApi> {
+    pub tx_hash: ManagedByteArray<M, 32>,
+    pub action_index: u32,
+    pub nonce_lane: NonceLane,
+    pub opt_nonce: Option<Nonce>,
+    pub opt_intent_id: Option<IntentId>,
+}
+
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode, Clone, Copy, PartialEq)]
+pub enum ActionStatus {
+    Success,
+    Failure,
+    /// The call went through, but returned less than the action's min returns
+    MinReturnsNotMet,
+}
+
+/// Error fields are only set on failure, returned payments only if the call went through
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode)]
+pub struct ActionOutcome<M: ManagedTypeApi> {
+    pub action_ref: ActionRef<M>,
+    pub status: ActionStatus,
+    pub error_code: u32,
+    pub error_message: ManagedBuffer<M>,
+    pub returned_payments: PaymentsVec<M>,
+}
+
+/// Keeps the results of the user's latest async actions,
+/// so relayers can tell which actions of a batch went through
+#[multiversx_sc::module]
+pub trait ActionOutcomesModule:
+    super::users::UsersModule + super::signature::SignatureModule + super::events::EventsModule
+{
+    /// Pairs of (outcome ID, outcome), oldest first
+    #[view(getActionOutcomes)]
+    fn get_action_outcomes(
+        &self,
+        user_address: ManagedAddress,
+    ) -> MultiValueEncoded<MultiValue2<OutcomeId, ActionOutcome<Self::Api>>> {
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let last_outcome_id = self.user_last_outcome_id(user_id).get();
+        let first_outcome_id = if last_outcome_id > MAX_USER_OUTCOMES {
+            last_outcome_id - MAX_USER_OUTCOMES + 1
+        } else {
+            1
+        };
+
+        let mut result = MultiValueEncoded::new();
+        for outcome_id in first_outcome_id..=last_outcome_id {
+            let outcome = self
+                .action_outcome(user_id, outcome_id % MAX_USER_OUTCOMES)
+                .get();
+            result.push((outcome_id, outcome).into());
+        }
+
+        result
+    }
+
+    fn store_action_outcome(
+        &self,
+        user_address: &ManagedAddress,
+        outcome: ActionOutcome<Self::Api>,
+    ) {
+        let user_id = self.user_ids().get_id_non_zero(user_address);
+        let outcome_id = self
+            .user_last_outcome_id(user_id)
+            .update(|last_outcome_id| {
+                *last_outcome_id += 1;
+
+                *last_outcome_id
+            });
+
+        match outcome.status {
+            ActionStatus::Success | ActionStatus::MinReturnsNotMet => {
+                self.async_action_succeeded_event(user_address, outcome_id, &outcome)
+            }
+            ActionStatus::Failure => {
+                self.async_action_failed_event(user_address, outcome_id, &outcome)
+            }
+        }
+
+        self.action_outcome(user_id, outcome_id % MAX_USER_OUTCOMES)
+            .set(outcome);
+    }
+
+    #[storage_mapper("userLastOutcomeId")]
+    fn user_last_outcome_id(&self, user_id: AddressId) -> SingleValueMapper<OutcomeId>;
+
+    #[storage_mapper("actionOutcome")]
+    fn action_outcome(
+        &self,
+        user_id: AddressId,
+        slot: OutcomeId,
+    ) -> SingleValueMapper<ActionOutcome<Self::Api>>;
+}
@@ -1,4 +1,4 @@
-use super::signature::Signature;
+use super::signature::KeySignatures;
 use mergeable::Mergeable;
 
 multiversx_sc::imports!();
@@ -6,8 +6,11 @@ multiversx_sc::derive_imports!();
 
 pub type PaymentsVec<M> = ManagedVec<M, EsdtTokenPayment<M>>;
 pub type Nonce = u64;
+pub type NonceLane = u64;
 pub type GasLimit = u64;
-pub type ActionMultiValue<M> = MultiValue3<GeneralActionData<M>, Nonce, Signature<M>>;
+pub type Timestamp = u64;
+pub type ActionMultiValue<M> =
+    MultiValue4<GeneralActionData<M>, NonceLane, Nonce, KeySignatures<M>>;
 pub type EsdtTxType<M> = Tx<
     TxScEnv<M>,
     (),
@@ -29,20 +32,32 @@ pub type EgldTxType<M> = Tx<
 
 pub static EGLD_TOKEN_ID: &[u8] = b"EGLD";
 
+/// Uses the user's original sequential nonce
+pub const DEFAULT_NONCE_LANE: NonceLane = 0;
+
+pub const SPENDING_WINDOW_BUCKETS: Timestamp = 24;
+
 #[derive(TypeAbi, TopEncode, TopDecode, NestedDecode, NestedEncode, ManagedVecItem)]
 pub struct ActionStruct<M: ManagedTypeApi> {
     pub action: GeneralActionData<M>,
+    pub nonce_lane: NonceLane,
     pub user_nonce: Nonce,
-    pub signature: Signature<M>,
+    pub signatures: KeySignatures<M>,
 }
 
 impl<M: ManagedTypeApi> ActionStruct<M> {
     #[inline]
-    pub fn new(action: GeneralActionData<M>, user_nonce: Nonce, signature: Signature<M>) -> Self {
+    pub fn new(
+        action: GeneralActionData<M>,
+        nonce_lane: NonceLane,
+        user_nonce: Nonce,
+        signatures: KeySignatures<M>,
+    ) -> Self {
         Self {
             action,
+            nonce_lane,
             user_nonce,
-            signature,
+            signatures,
         }
     }
 }
@@ -50,9 +65,13 @@ impl<M: ManagedTypeApi> ActionStruct<M> {
 pub trait Action<M: ManagedTypeApi>: ManagedVecItem {
     fn get_general_action_data(self) -> GeneralActionData<M>;
 
+    fn get_nonce_lane(&self) -> NonceLane;
+
     fn get_opt_nonce(&self) -> Option<Nonce>;
 
-    fn get_opt_signature(&self) -> Option<Signature<M>>;
+    fn get_opt_signatures(&self) -> Option<KeySignatures<M>>;
+
+    fn get_opt_extra_signed_data(&self) -> Option<ManagedBuffer<M>>;
 }
 
 impl<M: ManagedTypeApi> Action<M> for ActionStruct<M> {
@@ -60,12 +79,20 @@ impl<M: ManagedTypeApi> Action<M> for ActionStruct<M> {
         self.action
     }
 
+    fn get_nonce_lane(&self) -> NonceLane {
+        self.nonce_lane
+    }
+
     fn get_opt_nonce(&self) -> Option<Nonce> {
         Some(self.user_nonce)
     }
 
-    fn get_opt_signature(&self) -> Option<Signature<M>> {
-        Some(self.signature.clone())
+    fn get_opt_signatures(&self) -> Option<KeySignatures<M>> {
+        Some(self.signatures.clone())
+    }
+
+    fn get_opt_extra_signed_data(&self) -> Option<ManagedBuffer<M>> {
+        None
     }
 }
 
@@ -96,12 +123,231 @@ pub struct ScExecutionData<M: ManagedTypeApi> {
     pub gas_limit: GasLimit,
 }
 
-#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode, ManagedVecItem)]
+/// Max outflow of a token within any rolling window of the given length
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode, Clone)]
+pub struct SpendingLimit<M: ManagedTypeApi> {
+    pub amount: BigUint<M>,
+    pub window: Timestamp,
+}
+
+impl<M: ManagedTypeApi> SpendingLimit<M> {
+    pub fn is_at_least_as_strict_as(&self, other: &Self) -> bool {
+        self.amount <= other.amount && self.window >= other.window
+    }
+}
+
+/// Outflows within the same slice of the window are tracked together
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode, Clone, ManagedVecItem)]
+pub struct SpendingBucket<M: ManagedTypeApi> {
+    pub end: Timestamp,
+    pub spent: BigUint<M>,
+}
+
+/// Buckets are kept until a whole window elapsed since their end,
+/// so an outflow never stops counting before the window elapsed since it happened
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode)]
+pub struct SpendingWindow<M: ManagedTypeApi> {
+    pub buckets: ManagedVec<M, SpendingBucket<M>>,
+}
+
+impl<M: ManagedTypeApi> Default for SpendingWindow<M> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M: ManagedTypeApi> SpendingWindow<M> {
+    #[inline]
+    pub fn new() -> Self {
+        SpendingWindow {
+            buckets: ManagedVec::new(),
+        }
+    }
+
+    pub fn remove_expired_buckets(&mut self, window: Timestamp, current_timestamp: Timestamp) {
+        let mut active_buckets = ManagedVec::new();
+        for bucket in &self.buckets {
+            if bucket.end + window > current_timestamp {
+                active_buckets.push(bucket);
+            }
+        }
+
+        self.buckets = active_buckets;
+    }
+
+    pub fn get_spent(&self) -> BigUint<M> {
+        let mut spent = BigUint::zero();
+        for bucket in &self.buckets {
+            spent += bucket.spent;
+        }
+
+        spent
+    }
+
+    pub fn add_spent(
+        &mut self,
+        amount: &BigUint<M>,
+        window: Timestamp,
+        current_timestamp: Timestamp,
+    ) {
+        let len = self.buckets.len();
+        if len > 0 {
+            let mut last_bucket = self.buckets.get(len - 1);
+            if current_timestamp < last_bucket.end {
+                last_bucket.spent += amount;
+                let _ = self.buckets.set(len - 1, &last_bucket);
+
+                return;
+            }
+        }
+
+        let bucket_len = core::cmp::max(window / SPENDING_WINDOW_BUCKETS, 1);
+        self.buckets.push(SpendingBucket {
+            end: current_timestamp - current_timestamp % bucket_len + bucket_len,
+            spent: amount.clone(),
+        });
+    }
+
+    /// Buckets are sorted by end, so the amount is in the first bucket ending after `spent_at`.
+    /// Nothing is restored if that bucket no longer counts towards the limit.
+    pub fn restore_spent(&mut self, amount: &BigUint<M>, spent_at: Timestamp) {
+        let len = self.buckets.len();
+        for i in 0..len {
+            let mut bucket = self.buckets.get(i);
+            if bucket.end <= spent_at {
+                continue;
+            }
+
+            if &bucket.spent > amount {
+                bucket.spent -= amount;
+            } else {
+                bucket.spent = BigUint::zero();
+            }
+            let _ = self.buckets.set(i, &bucket);
+
+            return;
+        }
+    }
+}
+
+/// Paid to the fee recipient, or to the relayer if none is set
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode, Clone, ManagedVecItem)]
+pub struct RelayerFee<M: ManagedTypeApi> {
+    pub payment: EsdtTokenPayment<M>,
+    pub opt_recipient: Option<ManagedAddress<M>>,
+}
+
+/// Paid to the relayer from the paymaster's budget, instead of the user's balance.
+/// The period is the one the fee counted towards the paymaster's caps in.
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode, Clone, ManagedVecItem)]
+pub struct SponsoredFee<M: ManagedTypeApi> {
+    pub paymaster_address: ManagedAddress<M>,
+    pub payment: EsdtTokenPayment<M>,
+    pub period: u64,
+}
+
+/// The relayer fee and min returns are optional trailing fields.
+/// While empty, they are left out of the top-encoding, so actions encoded and signed
+/// in the original four field layout stay valid. Intents stored in that layout also decode.
+#[derive(TypeAbi, ManagedVecItem)]
 pub struct GeneralActionData<M: ManagedTypeApi> {
     pub call_type: CallType,
     pub dest_address: ManagedAddress<M>,
     pub payments: PaymentsVec<M>,
     pub opt_execution: Option<ScExecutionData<M>>,
+    pub opt_relayer_fee: Option<RelayerFee<M>>,
+    /// Empty if the action has no min returns
+    pub min_returns: PaymentsVec<M>,
+}
+
+impl<M: ManagedTypeApi> GeneralActionData<M> {
+    fn dep_encode_original_fields<O, H>(&self, dest: &mut O, h: H) -> Result<(), H::HandledErr>
+    where
+        O: codec::NestedEncodeOutput,
+        H: codec::EncodeErrorHandler,
+    {
+        self.call_type.dep_encode_or_handle_err(dest, h)?;
+        self.dest_address.dep_encode_or_handle_err(dest, h)?;
+        self.payments.dep_encode_or_handle_err(dest, h)?;
+        self.opt_execution.dep_encode_or_handle_err(dest, h)
+    }
+}
+
+impl<M: ManagedTypeApi> NestedEncode for GeneralActionData<M> {
+    fn dep_encode_or_handle_err<O, H>(&self, dest: &mut O, h: H) -> Result<(), H::HandledErr>
+    where
+        O: codec::NestedEncodeOutput,
+        H: codec::EncodeErrorHandler,
+    {
+        self.dep_encode_original_fields(dest, h)?;
+        self.opt_relayer_fee.dep_encode_or_handle_err(dest, h)?;
+        self.min_returns.dep_encode_or_handle_err(dest, h)
+    }
+}
+
+impl<M: ManagedTypeApi> TopEncode for GeneralActionData<M> {
+    fn top_encode_or_handle_err<O, H>(&self, output: O, h: H) -> Result<(), H::HandledErr>
+    where
+        O: codec::TopEncodeOutput,
+        H: codec::EncodeErrorHandler,
+    {
+        let mut buffer = output.start_nested_encode();
+        self.dep_encode_original_fields(&mut buffer, h)?;
+        if self.opt_relayer_fee.is_some() || !self.min_returns.is_empty() {
+            self.opt_relayer_fee
+                .dep_encode_or_handle_err(&mut buffer, h)?;
+        }
+        if !self.min_returns.is_empty() {
+            self.min_returns.dep_encode_or_handle_err(&mut buffer, h)?;
+        }
+        output.finalize_nested_encode(buffer);
+
+        Result::Ok(())
+    }
+}
+
+impl<M: ManagedTypeApi> NestedDecode for GeneralActionData<M> {
+    /// Trailing fields may only be missing at the end of the input
+    fn dep_decode_or_handle_err<I, H>(input: &mut I, h: H) -> Result<Self, H::HandledErr>
+    where
+        I: codec::NestedDecodeInput,
+        H: codec::DecodeErrorHandler,
+    {
+        let call_type = CallType::dep_decode_or_handle_err(input, h)?;
+        let dest_address = ManagedAddress::dep_decode_or_handle_err(input, h)?;
+        let payments = PaymentsVec::dep_decode_or_handle_err(input, h)?;
+        let opt_execution = Option::<ScExecutionData<M>>::dep_decode_or_handle_err(input, h)?;
+        let opt_relayer_fee = if input.is_depleted() {
+            None
+        } else {
+            Option::<RelayerFee<M>>::dep_decode_or_handle_err(input, h)?
+        };
+        let min_returns = if input.is_depleted() {
+            PaymentsVec::new()
+        } else {
+            PaymentsVec::dep_decode_or_handle_err(input, h)?
+        };
+
+        Result::Ok(Self {
+            call_type,
+            dest_address,
+            payments,
+            opt_execution,
+            opt_relayer_fee,
+            min_returns,
+        })
+    }
+}
+
+impl<M: ManagedTypeApi> TopDecode for GeneralActionData<M> {
+    fn top_decode_or_handle_err<I, H>(input: I, h: H) -> Result<Self, H::HandledErr>
+    where
+        I: codec::TopDecodeInput,
+        H: codec::DecodeErrorHandler,
+    {
+        codec::top_decode_from_nested_or_handle_err(input, h)
+    }
 }
 
 impl<M: ManagedTypeApi> Action<M> for GeneralActionData<M> {
@@ -109,11 +355,19 @@ impl<M: ManagedTypeApi> Action<M> for GeneralActionData<M> {
         self
     }
 
+    fn get_nonce_lane(&self) -> NonceLane {
+        DEFAULT_NONCE_LANE
+    }
+
     fn get_opt_nonce(&self) -> Option<Nonce> {
         None
     }
 
-    fn get_opt_signature(&self) -> Option<Signature<M>> {
+    fn get_opt_signatures(&self) -> Option<KeySignatures<M>> {
+        None
+    }
+
+    fn get_opt_extra_signed_data(&self) -> Option<ManagedBuffer<M>> {
         None
     }
 }
@@ -128,6 +382,15 @@ pub enum CallType {
 }
 
 impl<M: ManagedTypeApi> GeneralActionData<M> {
+    pub fn get_payments_with_fee(&self) -> PaymentsVec<M> {
+        let mut all_payments = self.payments.clone();
+        if let Some(relayer_fee) = &self.opt_relayer_fee {
+            all_payments.push(relayer_fee.payment.clone());
+        }
+
+        all_payments
+    }
+
     pub fn is_banned_endpoint_name(&self) -> bool {
         match &self.opt_execution {
             Some(execution) => {
@@ -1,43 +1,129 @@
-use crate::user_actions::intents::{IntentId, IntentType};
+use crate::user_actions::{intents::IntentType, whitelist_storage::WhitelistActionRef};
 
-use super::common_types::PaymentsVec;
+use super::{
+    action_outcomes::{ActionOutcome, ActionRef, ActionStatus},
+    common_types::{PaymentsVec, RelayerFee, SponsoredFee, Timestamp, UniquePayments},
+    sessions::SessionId,
+};
 
 multiversx_sc::imports!();
+multiversx_sc::derive_imports!();
+
+/// The user's limits an action was charged against, restored if its async call fails
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode, Clone)]
+pub struct ChargedLimits<M: ManagedTypeApi> {
+    pub spent_at: Timestamp,
+    pub opt_session_id: Option<SessionId>,
+    pub opt_whitelist_action: Option<WhitelistActionRef<M>>,
+}
 
 #[multiversx_sc::module]
 pub trait CustomCallbacksModule:
     super::users::UsersModule
     + super::signature::SignatureModule
+    + super::sessions::SessionsModule
+    + super::events::EventsModule
+    + super::action_outcomes::ActionOutcomesModule
     + crate::user_actions::intent_storage::IntentStorageModule
+    + crate::user_actions::paymaster_storage::PaymasterStorageModule
+    + crate::user_actions::whitelist_storage::WhitelistStorageModule
 {
     #[callback]
     fn user_action_cb(
         &self,
         original_user: ManagedAddress,
         original_payments: PaymentsVec<Self::Api>,
-        opt_intent_id: Option<IntentId>,
+        charged_limits: ChargedLimits<Self::Api>,
+        action_ref: ActionRef<Self::Api>,
+        min_returns: PaymentsVec<Self::Api>,
+        relayer: ManagedAddress,
+        opt_relayer_fee: Option<RelayerFee<Self::Api>>,
+        opt_sponsored_fee: Option<SponsoredFee<Self::Api>>,
         #[call_result] call_result: ManagedAsyncCallResult<IgnoreValue>,
     ) {
+        let opt_intent_id = action_ref.opt_intent_id;
         match call_result {
             ManagedAsyncCallResult::Ok(_) => {
                 let payments = self.get_esdt_and_egld_payments();
+                // the call can no longer be reverted, so the user is credited either way
+                let status = if self.are_min_returns_met(&min_returns, &payments) {
+                    ActionStatus::Success
+                } else {
+                    self.min_returns_not_met_event(&original_user, &min_returns, &payments);
+                    ActionStatus::MinReturnsNotMet
+                };
+
                 self.add_user_funds(&original_user, &payments);
+                self.pay_relayer_fee(&relayer, opt_relayer_fee);
+                self.pay_sponsored_fee(&relayer, &original_user, opt_sponsored_fee);
 
                 if let Some(intent_id) = opt_intent_id {
                     let user_id = self.user_ids().get_id_non_zero(&original_user);
-                    let _ = self.all_user_intents(user_id).swap_remove(&intent_id);
-                    self.user_intent(user_id, intent_id).clear();
+                    self.remove_intent(user_id, intent_id);
                 }
+
+                let outcome = ActionOutcome {
+                    action_ref,
+                    status,
+                    error_code: 0,
+                    error_message: ManagedBuffer::new(),
+                    returned_payments: payments,
+                };
+                self.store_action_outcome(&original_user, outcome);
             }
-            ManagedAsyncCallResult::Err(_) => {
-                self.refund_user(&original_user, &original_payments);
+            ManagedAsyncCallResult::Err(err) => {
+                match opt_intent_id {
+                    // funds stay reserved for the intent, so it can be retried
+                    Some(intent_id) => {
+                        let user_id = self.user_ids().get_id_non_zero(&original_user);
+                        self.user_intent(user_id, intent_id).update(|intent| {
+                            intent.intent_type = IntentType::AwaitingExecution;
+                        });
+                    }
+                    None => {
+                        let user_id = self.user_ids().get_id_non_zero(&original_user);
+                        if let Some(whitelist_action) = &charged_limits.opt_whitelist_action {
+                            self.restore_whitelist_allowance(
+                                user_id,
+                                whitelist_action,
+                                &original_payments,
+                            );
+                        }
 
-                if let Some(intent_id) = opt_intent_id {
+                        let mut refund_payments = original_payments;
+                        if let Some(relayer_fee) = opt_relayer_fee {
+                            refund_payments.push(relayer_fee.payment);
+                        }
+
+                        // the spending limits only count what actually left the account
+                        self.restore_allowance(user_id, &refund_payments, charged_limits.spent_at);
+                        if let Some(session_id) = charged_limits.opt_session_id {
+                            self.restore_session_caps(user_id, session_id, &refund_payments);
+                        }
+                        self.refund_user(&original_user, &refund_payments);
+                    }
+                }
+
+                // the relayer is only paid for sponsored actions that succeed
+                if let Some(sponsored_fee) = opt_sponsored_fee {
                     let user_id = self.user_ids().get_id_non_zero(&original_user);
-                    self.user_intent(user_id, intent_id).update(|intent| {
-                        intent.intent_type = IntentType::AwaitingExecution;
-                    });
+                    self.release_paymaster_usage(user_id, &sponsored_fee);
+                    self.refund_paymaster_budget(&sponsored_fee);
+                    self.paymaster_fee_refunded_event(
+                        &sponsored_fee.paymaster_address,
+                        &original_user,
+                        &sponsored_fee.payment,
+                    );
                 }
+
+                let outcome = ActionOutcome {
+                    action_ref,
+                    status: ActionStatus::Failure,
+                    error_code: err.err_code,
+                    error_message: err.err_msg,
+                    returned_payments: PaymentsVec::new(),
+                };
+                self.store_action_outcome(&original_user, outcome);
             }
         }
     }
@@ -51,6 +137,59 @@ pub trait CustomCallbacksModule:
         });
     }
 
+    fn are_min_returns_met(
+        &self,
+        min_returns: &PaymentsVec<Self::Api>,
+        received_payments: &PaymentsVec<Self::Api>,
+    ) -> bool {
+        if min_returns.is_empty() {
+            return true;
+        }
+
+        let mut received = UniquePayments::new_from_payments(received_payments.clone());
+        for min_return in min_returns {
+            if received.deduct_payment(&min_return).is_err() {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    fn pay_relayer_fee(
+        &self,
+        relayer: &ManagedAddress,
+        opt_relayer_fee: Option<RelayerFee<Self::Api>>,
+    ) {
+        if let Some(relayer_fee) = opt_relayer_fee {
+            let fee_recipient = relayer_fee.opt_recipient.unwrap_or_else(|| relayer.clone());
+            self.send_esdt_and_egld_payments(
+                &fee_recipient,
+                PaymentsVec::from_single_item(relayer_fee.payment),
+            );
+        }
+    }
+
+    fn pay_sponsored_fee(
+        &self,
+        relayer: &ManagedAddress,
+        user_address: &ManagedAddress,
+        opt_sponsored_fee: Option<SponsoredFee<Self::Api>>,
+    ) {
+        if let Some(sponsored_fee) = opt_sponsored_fee {
+            self.paymaster_fee_paid_event(
+                &sponsored_fee.paymaster_address,
+                user_address,
+                relayer,
+                &sponsored_fee.payment,
+            );
+            self.send_esdt_and_egld_payments(
+                relayer,
+                PaymentsVec::from_single_item(sponsored_fee.payment),
+            );
+        }
+    }
+
     #[inline]
     fn add_user_funds(&self, user: &ManagedAddress, payments: &PaymentsVec<Self::Api>) {
         self.refund_user(user, payments);
@@ -0,0 +1,302 @@
+use crate::user_actions::{
+    intents::IntentId, paymaster::PaymasterPolicy, whitelist_actions::WhitelistAction,
+};
+
+use super::{
+    action_outcomes::{ActionOutcome, OutcomeId},
+    common_types::{CallType, Nonce, NonceLane, PaymentsVec, SpendingLimit, Timestamp},
+    sessions::{SessionId, SessionKey},
+    signature::{KeyId, SigningKey},
+};
+
+multiversx_sc::imports!();
+
+#[multiversx_sc::module]
+pub trait EventsModule {
+    #[event("userRegistered")]
+    fn user_registered_event(
+        &self,
+        #[indexed] user_address: &ManagedAddress,
+        #[indexed] user_id: AddressId,
+    );
+
+    #[event("userDeposit")]
+    fn user_deposit_event(
+        &self,
+        #[indexed] user_address: &ManagedAddress,
+        #[indexed] depositor: &ManagedAddress,
+        payments: &PaymentsVec<Self::Api>,
+    );
+
+    #[event("userWithdraw")]
+    fn user_withdraw_event(
+        &self,
+        #[indexed] user_address: &ManagedAddress,
+        #[indexed] receiver: &ManagedAddress,
+        payments: &PaymentsVec<Self::Api>,
+    );
+
+    #[event("userKeyAdded")]
+    fn user_key_added_event(
+        &self,
+        #[indexed] user_address: &ManagedAddress,
+        #[indexed] key_id: KeyId,
+        key: &SigningKey<Self::Api>,
+    );
+
+    #[event("userKeyRemoved")]
+    fn user_key_removed_event(
+        &self,
+        #[indexed] user_address: &ManagedAddress,
+        #[indexed] key_id: KeyId,
+    );
+
+    #[event("userKeyRotated")]
+    fn user_key_rotated_event(
+        &self,
+        #[indexed] user_address: &ManagedAddress,
+        #[indexed] key_id: KeyId,
+        new_key: &SigningKey<Self::Api>,
+    );
+
+    #[event("signatureThresholdSet")]
+    fn signature_threshold_set_event(
+        &self,
+        #[indexed] user_address: &ManagedAddress,
+        #[indexed] threshold: u32,
+    );
+
+    #[event("sessionKeyAuthorized")]
+    fn session_key_authorized_event(
+        &self,
+        #[indexed] user_address: &ManagedAddress,
+        #[indexed] session_id: SessionId,
+        session: &SessionKey<Self::Api>,
+    );
+
+    #[event("sessionKeyRevoked")]
+    fn session_key_revoked_event(
+        &self,
+        #[indexed] user_address: &ManagedAddress,
+        #[indexed] session_id: SessionId,
+    );
+
+    #[event("spendingLimitSet")]
+    fn spending_limit_set_event(
+        &self,
+        #[indexed] user_address: &ManagedAddress,
+        #[indexed] token_id: &TokenIdentifier,
+        opt_limit: &Option<SpendingLimit<Self::Api>>,
+    );
+
+    #[event("spendingLimitChangeScheduled")]
+    fn spending_limit_change_scheduled_event(
+        &self,
+        #[indexed] user_address: &ManagedAddress,
+        #[indexed] token_id: &TokenIdentifier,
+        #[indexed] effective_after: Timestamp,
+        opt_limit: &Option<SpendingLimit<Self::Api>>,
+    );
+
+    #[event("spendingLimitChangeApproved")]
+    fn spending_limit_change_approved_event(
+        &self,
+        #[indexed] user_address: &ManagedAddress,
+        #[indexed] token_id: &TokenIdentifier,
+        #[indexed] guardian: &ManagedAddress,
+        #[indexed] nr_approvals: u32,
+    );
+
+    /// Async calls report their outcome through the async action events
+    #[event("actionExecuted")]
+    fn action_executed_event(
+        &self,
+        #[indexed] user_address: &ManagedAddress,
+        #[indexed] user_id: AddressId,
+        #[indexed] nonce_lane: NonceLane,
+        #[indexed] opt_nonce: Option<Nonce>,
+        #[indexed] action_index: u32,
+        #[indexed] call_type: CallType,
+        #[indexed] dest_address: &ManagedAddress,
+        payments: &PaymentsVec<Self::Api>,
+    );
+
+    /// Also emitted when the call returned less than the action's min returns
+    #[event("asyncActionSucceeded")]
+    fn async_action_succeeded_event(
+        &self,
+        #[indexed] user_address: &ManagedAddress,
+        #[indexed] outcome_id: OutcomeId,
+        outcome: &ActionOutcome<Self::Api>,
+    );
+
+    /// Payments are refunded to the user, or stay reserved for the intent
+    #[event("asyncActionFailed")]
+    fn async_action_failed_event(
+        &self,
+        #[indexed] user_address: &ManagedAddress,
+        #[indexed] outcome_id: OutcomeId,
+        outcome: &ActionOutcome<Self::Api>,
+    );
+
+    #[event("intentSaved")]
+    fn intent_saved_event(
+        &self,
+        #[indexed] user_address: &ManagedAddress,
+        #[indexed] intent_id: IntentId,
+        #[indexed] dest_address: &ManagedAddress,
+        payments: &PaymentsVec<Self::Api>,
+    );
+
+    #[event("intentExecuted")]
+    fn intent_executed_event(
+        &self,
+        #[indexed] user_address: &ManagedAddress,
+        #[indexed] intent_id: IntentId,
+        #[indexed] dest_address: &ManagedAddress,
+        payments: &PaymentsVec<Self::Api>,
+    );
+
+    #[event("intentCancelled")]
+    fn intent_cancelled_event(
+        &self,
+        #[indexed] user_address: &ManagedAddress,
+        #[indexed] intent_id: IntentId,
+    );
+
+    #[event("intentExpired")]
+    fn intent_expired_event(
+        &self,
+        #[indexed] user_address: &ManagedAddress,
+        #[indexed] intent_id: IntentId,
+    );
+
+    #[event("whitelistSet")]
+    fn whitelist_set_event(
+        &self,
+        #[indexed] user_address: &ManagedAddress,
+        #[indexed] whitelist_address: &ManagedAddress,
+        action_types: &ManagedVec<WhitelistAction<Self::Api>>,
+    );
+
+    /// Also emitted for expired entries when they are pruned
+    #[event("whitelistRemoved")]
+    fn whitelist_removed_event(
+        &self,
+        #[indexed] user_address: &ManagedAddress,
+        #[indexed] whitelist_address: &ManagedAddress,
+        action_types: &ManagedVec<WhitelistAction<Self::Api>>,
+    );
+
+    #[event("paymasterBudgetDeposit")]
+    fn paymaster_budget_deposit_event(
+        &self,
+        #[indexed] paymaster_address: &ManagedAddress,
+        payments: &PaymentsVec<Self::Api>,
+    );
+
+    #[event("paymasterBudgetWithdraw")]
+    fn paymaster_budget_withdraw_event(
+        &self,
+        #[indexed] paymaster_address: &ManagedAddress,
+        payments: &PaymentsVec<Self::Api>,
+    );
+
+    #[event("paymasterPolicySet")]
+    fn paymaster_policy_set_event(
+        &self,
+        #[indexed] paymaster_address: &ManagedAddress,
+        policy: &PaymasterPolicy<Self::Api>,
+    );
+
+    #[event("paymasterFeePaid")]
+    fn paymaster_fee_paid_event(
+        &self,
+        #[indexed] paymaster_address: &ManagedAddress,
+        #[indexed] user_address: &ManagedAddress,
+        #[indexed] relayer: &ManagedAddress,
+        fee: &EsdtTokenPayment,
+    );
+
+    #[event("paymasterFeeRefunded")]
+    fn paymaster_fee_refunded_event(
+        &self,
+        #[indexed] paymaster_address: &ManagedAddress,
+        #[indexed] user_address: &ManagedAddress,
+        fee: &EsdtTokenPayment,
+    );
+
+    #[event("sponsoredActionsAdded")]
+    fn sponsored_actions_added_event(
+        &self,
+        #[indexed] paymaster_address: &ManagedAddress,
+        action_types: &ManagedVec<WhitelistAction<Self::Api>>,
+    );
+
+    #[event("sponsoredActionsRemoved")]
+    fn sponsored_actions_removed_event(
+        &self,
+        #[indexed] paymaster_address: &ManagedAddress,
+        action_types: &ManagedVec<WhitelistAction<Self::Api>>,
+    );
+
+    #[event("minReturnsNotMet")]
+    fn min_returns_not_met_event(
+        &self,
+        #[indexed] user_address: &ManagedAddress,
+        #[indexed] min_returns: &PaymentsVec<Self::Api>,
+        received_payments: &PaymentsVec<Self::Api>,
+    );
+
+    #[event("guardiansSet")]
+    fn guardians_set_event(
+        &self,
+        #[indexed] user_address: &ManagedAddress,
+        #[indexed] threshold: u32,
+        #[indexed] time_lock: Timestamp,
+        guardians: &ManagedVec<ManagedAddress>,
+    );
+
+    #[event("recoveryProposed")]
+    fn recovery_proposed_event(
+        &self,
+        #[indexed] user_address: &ManagedAddress,
+        #[indexed] guardian: &ManagedAddress,
+        new_key: &SigningKey<Self::Api>,
+    );
+
+    #[event("recoveryApproved")]
+    fn recovery_approved_event(
+        &self,
+        #[indexed] user_address: &ManagedAddress,
+        #[indexed] guardian: &ManagedAddress,
+        #[indexed] nr_approvals: u32,
+    );
+
+    #[event("recoveryTimeLockStarted")]
+    fn recovery_time_lock_started_event(
+        &self,
+        #[indexed] user_address: &ManagedAddress,
+        #[indexed] effective_after: Timestamp,
+    );
+
+    #[event("recoveryCancelled")]
+    fn recovery_cancelled_event(&self, #[indexed] user_address: &ManagedAddress);
+
+    #[event("recoveryExecuted")]
+    fn recovery_executed_event(
+        &self,
+        #[indexed] user_address: &ManagedAddress,
+        new_key: &SigningKey<Self::Api>,
+    );
+
+    /// Nonces from `first_nonce` up to, but excluding, `up_to` were not used and can be dropped
+    #[event("noncesInvalidated")]
+    fn nonces_invalidated_event(
+        &self,
+        #[indexed] user_address: &ManagedAddress,
+        #[indexed] nonce_lane: NonceLane,
+        #[indexed] first_nonce: Nonce,
+        #[indexed] up_to: Nonce,
+    );
+}
@@ -0,0 +1,133 @@
+use super::{
+    common_types::Nonce,
+    signature::{KeyId, KeyOperation, KeySignatures, MessageKind, SigningKey},
+};
+
+multiversx_sc::imports!();
+
+/// Key updates must be signed by one of the user's current keys,
+/// so a compromised device can be replaced without migrating funds
+#[multiversx_sc::module]
+pub trait KeysModule:
+    super::users::UsersModule + super::signature::SignatureModule + super::events::EventsModule
+{
+    #[endpoint(addUserKey)]
+    fn add_user_key(
+        &self,
+        user_address: ManagedAddress,
+        new_key: SigningKey<Self::Api>,
+        user_nonce: Nonce,
+        signatures: KeySignatures<Self::Api>,
+    ) -> KeyId {
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let operation = KeyOperation::Add {
+            new_key: new_key.clone(),
+        };
+        self.check_key_operation(user_id, &user_address, user_nonce, &operation, &signatures);
+        self.store_legacy_address_key(user_id, &user_address);
+
+        let key_id = self.insert_user_key(user_id, new_key.clone());
+        self.user_key_added_event(&user_address, key_id, &new_key);
+
+        key_id
+    }
+
+    #[endpoint(removeUserKey)]
+    fn remove_user_key(
+        &self,
+        user_address: ManagedAddress,
+        key_id: KeyId,
+        user_nonce: Nonce,
+        signatures: KeySignatures<Self::Api>,
+    ) {
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let operation = KeyOperation::Remove { key_id };
+        self.check_key_operation(user_id, &user_address, user_nonce, &operation, &signatures);
+        self.store_legacy_address_key(user_id, &user_address);
+
+        self.delete_user_key(user_id, key_id);
+
+        self.user_key_removed_event(&user_address, key_id);
+    }
+
+    #[endpoint(rotateUserKey)]
+    fn rotate_user_key(
+        &self,
+        user_address: ManagedAddress,
+        key_id: KeyId,
+        new_key: SigningKey<Self::Api>,
+        user_nonce: Nonce,
+        signatures: KeySignatures<Self::Api>,
+    ) {
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let operation = KeyOperation::Rotate {
+            key_id,
+            new_key: new_key.clone(),
+        };
+        self.check_key_operation(user_id, &user_address, user_nonce, &operation, &signatures);
+        self.store_legacy_address_key(user_id, &user_address);
+
+        self.replace_user_key(user_id, key_id, new_key.clone());
+
+        self.user_key_rotated_event(&user_address, key_id, &new_key);
+    }
+
+    /// Number of distinct key signatures required for every signed operation
+    #[endpoint(setSignatureThreshold)]
+    fn set_signature_threshold(
+        &self,
+        user_address: ManagedAddress,
+        threshold: u32,
+        user_nonce: Nonce,
+        signatures: KeySignatures<Self::Api>,
+    ) {
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let operation = KeyOperation::SetThreshold { threshold };
+        self.check_key_operation(user_id, &user_address, user_nonce, &operation, &signatures);
+        self.store_legacy_address_key(user_id, &user_address);
+
+        self.update_signature_threshold(user_id, threshold);
+
+        self.signature_threshold_set_event(&user_address, threshold);
+    }
+
+    #[view(getUserKeys)]
+    fn get_user_keys(
+        &self,
+        user_address: ManagedAddress,
+    ) -> MultiValueEncoded<MultiValue2<KeyId, SigningKey<Self::Api>>> {
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let mut result = MultiValueEncoded::new();
+        for key_id in self.user_key_ids(user_id).iter() {
+            let signing_key = self.user_key(user_id, key_id).get();
+            result.push((key_id, signing_key).into());
+        }
+
+        result
+    }
+
+    #[view(getSignatureThreshold)]
+    fn get_signature_threshold(&self, user_address: ManagedAddress) -> u32 {
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        self.signature_threshold_or_default(user_id)
+    }
+
+    fn check_key_operation(
+        &self,
+        user_id: AddressId,
+        user_address: &ManagedAddress,
+        user_nonce: Nonce,
+        operation: &KeyOperation<Self::Api>,
+        signatures: &KeySignatures<Self::Api>,
+    ) {
+        self.consume_user_nonce(user_id, user_nonce);
+        self.check_operation_signature(
+            user_id,
+            user_address,
+            user_nonce,
+            MessageKind::UpdateKeys,
+            operation,
+            signatures,
+        );
+    }
+}
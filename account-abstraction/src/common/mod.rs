@@ -1,4 +1,11 @@
+pub mod action_outcomes;
 pub mod common_types;
 pub mod custom_callbacks;
+pub mod events;
+pub mod keys;
+pub mod nonces;
+pub mod recovery;
+pub mod sessions;
 pub mod signature;
+pub mod spending_limits;
 pub mod users;
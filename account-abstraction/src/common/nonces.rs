@@ -0,0 +1,76 @@
+use super::{
+    common_types::{Nonce, NonceLane},
+    signature::{KeySignatures, MessageKind},
+};
+
+multiversx_sc::imports!();
+multiversx_sc::derive_imports!();
+
+/// Keeps nonces far from overflowing, which would make old signatures valid again
+pub const MAX_NONCE_INCREASE: Nonce = 1_000_000_000;
+
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode)]
+pub enum NonceOperation {
+    InvalidateNonces { nonce_lane: NonceLane, up_to: Nonce },
+}
+
+/// Lets users revoke actions they signed but that were not submitted yet
+#[multiversx_sc::module]
+pub trait NoncesModule:
+    super::users::UsersModule + super::signature::SignatureModule + super::events::EventsModule
+{
+    /// Every nonce of the lane lower than `up_to` becomes invalid.
+    /// The user may call directly while the address key can sign alone, otherwise a (nonce, signature) pair is required.
+    /// The signature always consumes a nonce of the default lane.
+    #[endpoint(invalidateNonces)]
+    fn invalidate_nonces(
+        &self,
+        user_address: ManagedAddress,
+        nonce_lane: NonceLane,
+        up_to: Nonce,
+        opt_signatures: OptionalValue<MultiValue2<Nonce, KeySignatures<Self::Api>>>,
+    ) {
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let caller = self.blockchain().get_caller();
+        if caller != user_address {
+            let (user_nonce, signatures) = match opt_signatures {
+                OptionalValue::Some(multi_value) => multi_value.into_tuple(),
+                OptionalValue::None => sc_panic!("Signature required"),
+            };
+            let operation = NonceOperation::InvalidateNonces { nonce_lane, up_to };
+            self.check_nonce_operation(user_id, &user_address, user_nonce, &operation, &signatures);
+        } else {
+            self.require_address_key_authority(user_id, &user_address);
+        }
+
+        let nonce_mapper = self.lane_nonce(user_id, nonce_lane);
+        let current_nonce = nonce_mapper.get();
+        require!(
+            up_to > current_nonce && up_to - current_nonce <= MAX_NONCE_INCREASE,
+            "Invalid nonce"
+        );
+
+        nonce_mapper.set(up_to);
+
+        self.nonces_invalidated_event(&user_address, nonce_lane, current_nonce, up_to);
+    }
+
+    fn check_nonce_operation(
+        &self,
+        user_id: AddressId,
+        user_address: &ManagedAddress,
+        user_nonce: Nonce,
+        operation: &NonceOperation,
+        signatures: &KeySignatures<Self::Api>,
+    ) {
+        self.consume_user_nonce(user_id, user_nonce);
+        self.check_operation_signature(
+            user_id,
+            user_address,
+            user_nonce,
+            MessageKind::InvalidateNonces,
+            operation,
+            signatures,
+        );
+    }
+}
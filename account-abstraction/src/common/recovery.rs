@@ -0,0 +1,272 @@
+use super::{
+    common_types::{Nonce, Timestamp},
+    signature::{KeySignatures, MessageKind, SigningKey},
+};
+
+multiversx_sc::imports!();
+multiversx_sc::derive_imports!();
+
+pub const MAX_GUARDIANS: usize = 10;
+
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode)]
+pub struct RecoveryConfig {
+    pub threshold: u32,
+    pub time_lock: Timestamp,
+}
+
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode)]
+pub enum GuardianOperation<M: ManagedTypeApi> {
+    SetGuardians {
+        guardians: ManagedVec<M, ManagedAddress<M>>,
+        threshold: u32,
+        time_lock: Timestamp,
+    },
+    CancelRecovery,
+}
+
+/// The time-lock starts once enough guardians approved the request
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode)]
+pub struct RecoveryRequest<M: ManagedTypeApi> {
+    pub new_key: SigningKey<M>,
+    pub approvals: ManagedVec<M, ManagedAddress<M>>,
+    pub opt_effective_after: Option<Timestamp>,
+}
+
+/// Guardians can jointly replace the user's whole key set with a new key.
+/// Until the time-lock expires, the current keys can cancel the recovery.
+#[multiversx_sc::module]
+pub trait RecoveryModule:
+    super::users::UsersModule
+    + super::signature::SignatureModule
+    + super::sessions::SessionsModule
+    + super::events::EventsModule
+{
+    /// An empty guardians list disables recovery
+    #[endpoint(setGuardians)]
+    fn set_guardians(
+        &self,
+        user_address: ManagedAddress,
+        threshold: u32,
+        time_lock: Timestamp,
+        user_nonce: Nonce,
+        signatures: KeySignatures<Self::Api>,
+        guardians: MultiValueEncoded<ManagedAddress>,
+    ) {
+        let guardians_vec = guardians.to_vec();
+        require!(guardians_vec.len() <= MAX_GUARDIANS, "Too many guardians");
+        require!(
+            guardians_vec.is_empty()
+                || (threshold > 0 && threshold as usize <= guardians_vec.len()),
+            "Invalid threshold"
+        );
+
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let operation = GuardianOperation::SetGuardians {
+            guardians: guardians_vec.clone(),
+            threshold,
+            time_lock,
+        };
+        self.check_guardian_operation(user_id, &user_address, user_nonce, &operation, &signatures);
+
+        let mut guardians_mapper = self.user_guardians(user_id);
+        guardians_mapper.clear();
+        for guardian in &guardians_vec {
+            require!(*guardian != user_address, "User can't be own guardian");
+
+            let inserted = guardians_mapper.insert(guardian.clone_value());
+            require!(inserted, "Duplicate guardian");
+        }
+
+        self.recovery_config(user_id).set(RecoveryConfig {
+            threshold,
+            time_lock,
+        });
+        self.pending_recovery(user_id).clear();
+
+        self.guardians_set_event(&user_address, threshold, time_lock, &guardians_vec);
+    }
+
+    /// Counts as the first approval
+    #[endpoint(proposeRecovery)]
+    fn propose_recovery(&self, user_address: ManagedAddress, new_key: SigningKey<Self::Api>) {
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let caller = self.blockchain().get_caller();
+        self.require_guardian(user_id, &caller);
+
+        let pending_mapper = self.pending_recovery(user_id);
+        require!(pending_mapper.is_empty(), "Recovery already pending");
+        self.require_valid_key_format(&new_key);
+
+        let mut request = RecoveryRequest {
+            new_key,
+            approvals: ManagedVec::from_single_item(caller.clone()),
+            opt_effective_after: None,
+        };
+        self.recovery_proposed_event(&user_address, &caller, &request.new_key);
+        self.recovery_approved_event(&user_address, &caller, request.approvals.len() as u32);
+        self.try_start_time_lock(user_id, &user_address, &mut request);
+
+        pending_mapper.set(request);
+    }
+
+    #[endpoint(approveRecovery)]
+    fn approve_recovery(&self, user_address: ManagedAddress) {
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let caller = self.blockchain().get_caller();
+        self.require_guardian(user_id, &caller);
+
+        let pending_mapper = self.pending_recovery(user_id);
+        require!(!pending_mapper.is_empty(), "No pending recovery");
+
+        let mut request = pending_mapper.get();
+        require!(
+            request.opt_effective_after.is_none(),
+            "Recovery already approved"
+        );
+        require!(!request.approvals.contains(&caller), "Already approved");
+
+        request.approvals.push(caller.clone());
+        self.recovery_approved_event(&user_address, &caller, request.approvals.len() as u32);
+        self.try_start_time_lock(user_id, &user_address, &mut request);
+
+        pending_mapper.set(request);
+    }
+
+    /// Signed by the user's current keys
+    #[endpoint(cancelRecovery)]
+    fn cancel_recovery(
+        &self,
+        user_address: ManagedAddress,
+        user_nonce: Nonce,
+        signatures: KeySignatures<Self::Api>,
+    ) {
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let pending_mapper = self.pending_recovery(user_id);
+        require!(!pending_mapper.is_empty(), "No pending recovery");
+
+        self.check_guardian_operation(
+            user_id,
+            &user_address,
+            user_nonce,
+            &GuardianOperation::CancelRecovery,
+            &signatures,
+        );
+
+        pending_mapper.clear();
+
+        self.recovery_cancelled_event(&user_address);
+    }
+
+    /// Callable by anyone once the time-lock expired
+    #[endpoint(executeRecovery)]
+    fn execute_recovery(&self, user_address: ManagedAddress) {
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let pending_mapper = self.pending_recovery(user_id);
+        require!(!pending_mapper.is_empty(), "No pending recovery");
+
+        let request = pending_mapper.take();
+        let effective_after = match request.opt_effective_after {
+            Some(effective_after) => effective_after,
+            None => sc_panic!("Not enough approvals"),
+        };
+        let current_timestamp = self.blockchain().get_block_timestamp();
+        require!(current_timestamp >= effective_after, "Recovery time-locked");
+
+        // sessions were authorized by the lost keys
+        self.clear_user_keys(user_id);
+        self.revoke_all_sessions(user_id, &user_address);
+        let _ = self.insert_user_key(user_id, request.new_key.clone());
+
+        self.recovery_executed_event(&user_address, &request.new_key);
+    }
+
+    #[view(getGuardians)]
+    fn get_guardians(&self, user_address: ManagedAddress) -> MultiValueEncoded<ManagedAddress> {
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let mut guardians = MultiValueEncoded::new();
+        for guardian in self.user_guardians(user_id).iter() {
+            guardians.push(guardian);
+        }
+
+        guardians
+    }
+
+    #[view(getRecoveryConfig)]
+    fn get_recovery_config(&self, user_address: ManagedAddress) -> OptionalValue<RecoveryConfig> {
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let config_mapper = self.recovery_config(user_id);
+        if config_mapper.is_empty() {
+            return OptionalValue::None;
+        }
+
+        OptionalValue::Some(config_mapper.get())
+    }
+
+    #[view(getPendingRecovery)]
+    fn get_pending_recovery(
+        &self,
+        user_address: ManagedAddress,
+    ) -> OptionalValue<RecoveryRequest<Self::Api>> {
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let pending_mapper = self.pending_recovery(user_id);
+        if pending_mapper.is_empty() {
+            return OptionalValue::None;
+        }
+
+        OptionalValue::Some(pending_mapper.get())
+    }
+
+    fn require_guardian(&self, user_id: AddressId, address: &ManagedAddress) {
+        require!(
+            self.user_guardians(user_id).contains(address),
+            "Not a guardian"
+        );
+    }
+
+    fn check_guardian_operation(
+        &self,
+        user_id: AddressId,
+        user_address: &ManagedAddress,
+        user_nonce: Nonce,
+        operation: &GuardianOperation<Self::Api>,
+        signatures: &KeySignatures<Self::Api>,
+    ) {
+        self.consume_user_nonce(user_id, user_nonce);
+        self.check_operation_signature(
+            user_id,
+            user_address,
+            user_nonce,
+            MessageKind::UpdateGuardians,
+            operation,
+            signatures,
+        );
+    }
+
+    fn try_start_time_lock(
+        &self,
+        user_id: AddressId,
+        user_address: &ManagedAddress,
+        request: &mut RecoveryRequest<Self::Api>,
+    ) {
+        let config = self.recovery_config(user_id).get();
+        if request.approvals.len() < config.threshold as usize {
+            return;
+        }
+
+        let current_timestamp = self.blockchain().get_block_timestamp();
+        let effective_after = current_timestamp + config.time_lock;
+        request.opt_effective_after = Some(effective_after);
+
+        self.recovery_time_lock_started_event(user_address, effective_after);
+    }
+
+    #[storage_mapper("userGuardians")]
+    fn user_guardians(&self, user_id: AddressId) -> UnorderedSetMapper<ManagedAddress>;
+
+    #[storage_mapper("recoveryConfig")]
+    fn recovery_config(&self, user_id: AddressId) -> SingleValueMapper<RecoveryConfig>;
+
+    #[storage_mapper("pendingRecovery")]
+    fn pending_recovery(&self, user_id: AddressId)
+        -> SingleValueMapper<RecoveryRequest<Self::Api>>;
+}
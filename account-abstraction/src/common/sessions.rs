@@ -0,0 +1,266 @@
+use crate::user_actions::whitelist_actions::WhitelistAction;
+
+use super::{
+    common_types::{GeneralActionData, Nonce, PaymentsVec, Timestamp, UniquePayments},
+    signature::{KeySignatures, MessageKind, SigningKey},
+};
+
+multiversx_sc::imports!();
+multiversx_sc::derive_imports!();
+
+pub type SessionId = u32;
+
+pub const MAX_USER_SESSIONS: usize = 10;
+
+/// Transfers are allowed by an entry with the receiver and an empty endpoint name
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode, Clone)]
+pub struct SessionKey<M: ManagedTypeApi> {
+    pub signing_key: SigningKey<M>,
+    pub allowed_actions: ManagedVec<M, WhitelistAction<M>>,
+    pub spending_caps: PaymentsVec<M>,
+    pub expires_at: Timestamp,
+}
+
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode)]
+pub enum SessionOperation<M: ManagedTypeApi> {
+    AuthorizeSession { session: SessionKey<M> },
+    RevokeSession { session_id: SessionId },
+}
+
+/// Ephemeral keys, authorized once by the user,
+/// that may sign a restricted set of actions until they expire
+#[multiversx_sc::module]
+pub trait SessionsModule:
+    super::users::UsersModule + super::signature::SignatureModule + super::events::EventsModule
+{
+    #[endpoint(authorizeSessionKey)]
+    fn authorize_session_key(
+        &self,
+        user_address: ManagedAddress,
+        session: SessionKey<Self::Api>,
+        user_nonce: Nonce,
+        signatures: KeySignatures<Self::Api>,
+    ) -> SessionId {
+        let current_timestamp = self.blockchain().get_block_timestamp();
+        require!(session.expires_at > current_timestamp, "Invalid expiry");
+        require!(!session.allowed_actions.is_empty(), "No allowed actions");
+        self.require_valid_key_format(&session.signing_key);
+
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        self.remove_expired_sessions(user_id, &user_address, current_timestamp);
+
+        let mut session_ids_mapper = self.user_session_ids(user_id);
+        require!(
+            session_ids_mapper.len() < MAX_USER_SESSIONS,
+            "Too many sessions"
+        );
+
+        let operation = SessionOperation::AuthorizeSession {
+            session: session.clone(),
+        };
+        self.check_session_operation(user_id, &user_address, user_nonce, &operation, &signatures);
+
+        let session_id = self
+            .user_last_session_id(user_id)
+            .update(|last_session_id| {
+                *last_session_id += 1;
+
+                *last_session_id
+            });
+        let _ = session_ids_mapper.insert(session_id);
+        self.session_remaining_caps(user_id, session_id)
+            .set(UniquePayments::new_from_payments(
+                session.spending_caps.clone(),
+            ));
+        self.session_key_authorized_event(&user_address, session_id, &session);
+        self.session_key(user_id, session_id).set(session);
+
+        session_id
+    }
+
+    #[endpoint(revokeSessionKey)]
+    fn revoke_session_key(
+        &self,
+        user_address: ManagedAddress,
+        session_id: SessionId,
+        user_nonce: Nonce,
+        signatures: KeySignatures<Self::Api>,
+    ) {
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let operation = SessionOperation::RevokeSession { session_id };
+        self.check_session_operation(user_id, &user_address, user_nonce, &operation, &signatures);
+
+        let removed = self.user_session_ids(user_id).swap_remove(&session_id);
+        require!(removed, "Unknown session");
+
+        self.clear_session(user_id, session_id);
+
+        self.session_key_revoked_event(&user_address, session_id);
+    }
+
+    /// Triples of (session ID, session, remaining spending caps), expired sessions are skipped
+    #[view(getSessionKeys)]
+    fn get_session_keys(
+        &self,
+        user_address: ManagedAddress,
+    ) -> MultiValueEncoded<MultiValue3<SessionId, SessionKey<Self::Api>, PaymentsVec<Self::Api>>>
+    {
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let current_timestamp = self.blockchain().get_block_timestamp();
+        let mut result = MultiValueEncoded::new();
+        for session_id in self.user_session_ids(user_id).iter() {
+            let session = self.session_key(user_id, session_id).get();
+            if current_timestamp >= session.expires_at {
+                continue;
+            }
+
+            let remaining_caps = self
+                .session_remaining_caps(user_id, session_id)
+                .get()
+                .into_payments();
+            result.push((session_id, session, remaining_caps).into());
+        }
+
+        result
+    }
+
+    fn check_session_operation(
+        &self,
+        user_id: AddressId,
+        user_address: &ManagedAddress,
+        user_nonce: Nonce,
+        operation: &SessionOperation<Self::Api>,
+        signatures: &KeySignatures<Self::Api>,
+    ) {
+        self.consume_user_nonce(user_id, user_nonce);
+        self.check_operation_signature(
+            user_id,
+            user_address,
+            user_nonce,
+            MessageKind::UpdateSessions,
+            operation,
+            signatures,
+        );
+    }
+
+    fn get_active_session(
+        &self,
+        user_id: AddressId,
+        session_id: SessionId,
+    ) -> SessionKey<Self::Api> {
+        require!(
+            self.user_session_ids(user_id).contains(&session_id),
+            "Unknown session"
+        );
+
+        let session = self.session_key(user_id, session_id).get();
+        let current_timestamp = self.blockchain().get_block_timestamp();
+        require!(current_timestamp < session.expires_at, "Session expired");
+
+        session
+    }
+
+    fn require_session_allowed_action(
+        &self,
+        session: &SessionKey<Self::Api>,
+        action: &GeneralActionData<Self::Api>,
+    ) {
+        let endpoint_name = match &action.opt_execution {
+            Some(execution) => execution.endpoint_name.clone(),
+            None => ManagedBuffer::new(),
+        };
+        let is_allowed = session.allowed_actions.iter().any(|allowed_action| {
+            allowed_action.sc_address == action.dest_address
+                && allowed_action.endpoint_name == endpoint_name
+        });
+        require!(is_allowed, "Action not allowed for session");
+    }
+
+    fn deduct_session_caps(
+        &self,
+        payments: &PaymentsVec<Self::Api>,
+        remaining_caps: &mut UniquePayments<Self::Api>,
+    ) {
+        for payment in payments {
+            let deduct_result = remaining_caps.deduct_payment(&payment);
+            require!(deduct_result.is_ok(), "Session spending cap exceeded");
+        }
+    }
+
+    /// Used when the action's payments are refunded. Revoked sessions are not restored.
+    fn restore_session_caps(
+        &self,
+        user_id: AddressId,
+        session_id: SessionId,
+        payments: &PaymentsVec<Self::Api>,
+    ) {
+        if !self.user_session_ids(user_id).contains(&session_id) {
+            return;
+        }
+
+        self.session_remaining_caps(user_id, session_id)
+            .update(|remaining_caps| {
+                for payment in payments {
+                    remaining_caps.add_payment(payment);
+                }
+            });
+    }
+
+    /// Expired sessions can't sign anymore, so they don't count towards the sessions limit
+    fn remove_expired_sessions(
+        &self,
+        user_id: AddressId,
+        user_address: &ManagedAddress,
+        current_timestamp: Timestamp,
+    ) {
+        let mut session_ids_mapper = self.user_session_ids(user_id);
+        let mut expired_session_ids = ManagedVec::<Self::Api, SessionId>::new();
+        for session_id in session_ids_mapper.iter() {
+            let session = self.session_key(user_id, session_id).get();
+            if current_timestamp >= session.expires_at {
+                expired_session_ids.push(session_id);
+            }
+        }
+
+        for session_id in &expired_session_ids {
+            let _ = session_ids_mapper.swap_remove(&session_id);
+            self.clear_session(user_id, session_id);
+            self.session_key_revoked_event(user_address, session_id);
+        }
+    }
+
+    fn revoke_all_sessions(&self, user_id: AddressId, user_address: &ManagedAddress) {
+        let mut session_ids_mapper = self.user_session_ids(user_id);
+        for session_id in session_ids_mapper.iter() {
+            self.clear_session(user_id, session_id);
+            self.session_key_revoked_event(user_address, session_id);
+        }
+
+        session_ids_mapper.clear();
+    }
+
+    fn clear_session(&self, user_id: AddressId, session_id: SessionId) {
+        self.session_key(user_id, session_id).clear();
+        self.session_remaining_caps(user_id, session_id).clear();
+    }
+
+    #[storage_mapper("userSessionIds")]
+    fn user_session_ids(&self, user_id: AddressId) -> UnorderedSetMapper<SessionId>;
+
+    #[storage_mapper("userLastSessionId")]
+    fn user_last_session_id(&self, user_id: AddressId) -> SingleValueMapper<SessionId>;
+
+    #[storage_mapper("sessionKey")]
+    fn session_key(
+        &self,
+        user_id: AddressId,
+        session_id: SessionId,
+    ) -> SingleValueMapper<SessionKey<Self::Api>>;
+
+    #[storage_mapper("sessionRemainingCaps")]
+    fn session_remaining_caps(
+        &self,
+        user_id: AddressId,
+        session_id: SessionId,
+    ) -> SingleValueMapper<UniquePayments<Self::Api>>;
+}
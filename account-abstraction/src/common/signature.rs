@@ -1,40 +1,180 @@
-use super::common_types::{GeneralActionData, Nonce};
+use crate::user_actions::intents::IntentId;
+
+use super::{
+    common_types::{GeneralActionData, Nonce, NonceLane, PaymentsVec, DEFAULT_NONCE_LANE},
+    sessions::SessionId,
+};
 
 multiversx_sc::imports!();
+multiversx_sc::derive_imports!();
+
+pub const ED25519_SIGNATURE_LEN: usize = 64;
+pub const MAX_USER_KEYS: usize = 10;
+pub type Signature<M> = ManagedBuffer<M>;
+pub type KeyId = u32;
+pub type KeySignatures<M> = ManagedVec<M, KeySignature<M>>;
+
+/// The address key is the first key stored for every user
+pub const ADDRESS_KEY_ID: KeyId = 1;
 
-pub const SIGNATURE_LEN: usize = 64;
-pub type Signature<M> = ManagedByteArray<M, SIGNATURE_LEN>;
+const DEFAULT_SIGNATURE_THRESHOLD: u32 = 1;
 
-static REGISTER_ENDPOINT_NAME: &[u8] = b"registerUser";
 static FIELDS_SEPARATOR_CHAR: &[u8] = b"@";
 
 const FIRST_NONCE: Nonce = 0;
+pub const SIGNATURE_SCHEMA_VERSION: u8 = 1;
+
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode, Clone, Copy)]
+pub enum MessageKind {
+    Register,
+    Execution,
+    Batch,
+    Withdraw,
+    CancelIntent,
+    UpdateKeys,
+    AtomicBatch,
+    UpdateGuardians,
+    UpdateSessions,
+    UpdateSpendingLimits,
+    InvalidateNonces,
+}
+
+#[derive(
+    TypeAbi,
+    TopEncode,
+    TopDecode,
+    NestedEncode,
+    NestedDecode,
+    Clone,
+    Copy,
+    PartialEq,
+    ManagedVecItem,
+)]
+pub enum KeyType {
+    Ed25519,
+    Secp256k1,
+    Secp256r1,
+}
+
+impl KeyType {
+    pub fn is_valid_public_key_len(&self, key_len: usize) -> bool {
+        match self {
+            KeyType::Ed25519 => key_len == 32,
+            KeyType::Secp256k1 | KeyType::Secp256r1 => key_len == 33 || key_len == 65,
+        }
+    }
+
+    /// Secp256r1 keys can't be registered until the VM exposes a verification hook for them
+    pub fn is_supported(&self) -> bool {
+        !matches!(self, KeyType::Secp256r1)
+    }
+}
+
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode, Clone, ManagedVecItem)]
+pub struct SigningKey<M: ManagedTypeApi> {
+    pub key_type: KeyType,
+    pub public_key: ManagedBuffer<M>,
+}
+
+impl<M: ManagedTypeApi> SigningKey<M> {
+    #[inline]
+    pub fn from_address(address: &ManagedAddress<M>) -> Self {
+        Self {
+            key_type: KeyType::Ed25519,
+            public_key: address.as_managed_buffer().clone(),
+        }
+    }
+}
+
+/// Tells the verifier which of the user's keys produced the signature
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode, ManagedVecItem)]
+pub struct KeySignature<M: ManagedTypeApi> {
+    pub key_id: KeyId,
+    pub signature: Signature<M>,
+}
+
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode)]
+pub enum KeyOperation<M: ManagedTypeApi> {
+    Add {
+        new_key: SigningKey<M>,
+    },
+    Remove {
+        key_id: KeyId,
+    },
+    Rotate {
+        key_id: KeyId,
+        new_key: SigningKey<M>,
+    },
+    SetThreshold {
+        threshold: u32,
+    },
+}
+
+/// Prepended to every signed payload, so signatures can't be replayed
+/// on another chain, another contract, or for another kind of message
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode)]
+pub struct DomainSeparator<M: ManagedTypeApi> {
+    pub chain_id: ManagedBuffer<M>,
+    pub contract_address: ManagedAddress<M>,
+    pub schema_version: u8,
+    pub message_kind: MessageKind,
+}
 
 pub struct CheckExecutionSignatureArgs<'a, M: ManagedTypeApi> {
     pub own_sc_address: &'a ManagedAddress<M>,
+    pub user_id: AddressId,
     pub user_address: &'a ManagedAddress<M>,
+    pub nonce_lane: NonceLane,
     pub user_nonce: Nonce,
     pub action: &'a GeneralActionData<M>,
-    pub signature: &'a Signature<M>,
+    pub opt_extra_signed_data: Option<&'a ManagedBuffer<M>>,
+    pub opt_session: Option<(SessionId, &'a SigningKey<M>)>,
+    pub signatures: &'a KeySignatures<M>,
+}
+
+pub struct CheckWithdrawSignatureArgs<'a, M: ManagedTypeApi> {
+    pub own_sc_address: &'a ManagedAddress<M>,
+    pub user_id: AddressId,
+    pub user_address: &'a ManagedAddress<M>,
+    pub user_nonce: Nonce,
+    pub receiver: &'a ManagedAddress<M>,
+    pub payments: &'a PaymentsVec<M>,
+    pub signatures: &'a KeySignatures<M>,
+}
+
+pub struct CheckBatchSignatureArgs<'a, M: ManagedTypeApi> {
+    pub own_sc_address: &'a ManagedAddress<M>,
+    pub user_id: AddressId,
+    pub user_address: &'a ManagedAddress<M>,
+    pub user_nonce: Nonce,
+    pub actions: &'a ManagedVec<M, GeneralActionData<M>>,
+    pub atomic: bool,
+    pub signatures: &'a KeySignatures<M>,
 }
 
 #[multiversx_sc::module]
 pub trait SignatureModule {
+    fn set_chain_id(&self, chain_id: ManagedBuffer) {
+        require!(!chain_id.is_empty(), "Invalid chain ID");
+
+        self.chain_id().set(chain_id);
+    }
+
     fn check_register_signature(
         &self,
         user_address: &ManagedAddress,
         signature: &Signature<Self::Api>,
     ) {
         let own_sc_address = self.blockchain().get_sc_address();
-        let mut signature_data = ManagedBuffer::new_from_bytes(REGISTER_ENDPOINT_NAME);
-        signature_data.append_bytes(FIELDS_SEPARATOR_CHAR);
-        signature_data.append(user_address.as_managed_buffer());
-        signature_data.append_bytes(FIELDS_SEPARATOR_CHAR);
-        signature_data.append(own_sc_address.as_managed_buffer());
-        signature_data.append_bytes(FIELDS_SEPARATOR_CHAR);
-        signature_data.append_bytes(&FIRST_NONCE.to_be_bytes());
+        let signature_data = self.new_signature_data(
+            &own_sc_address,
+            MessageKind::Register,
+            user_address,
+            FIRST_NONCE,
+        );
 
-        self.check_sig(user_address, &signature_data, signature);
+        let address_key = SigningKey::from_address(user_address);
+        self.verify_key_sig(&address_key, &signature_data, signature);
     }
 
     fn check_execution_signature(&self, args: CheckExecutionSignatureArgs<Self::Api>) {
@@ -42,39 +182,449 @@ pub trait SignatureModule {
         let encode_result = args.action.top_encode(&mut serialized_action);
         require!(encode_result.is_ok(), "Encoding error");
 
-        let mut signature_data = ManagedBuffer::new();
-        signature_data.append(args.user_address.as_managed_buffer());
+        let mut signature_data = self.new_lane_signature_data(
+            args.own_sc_address,
+            MessageKind::Execution,
+            args.user_address,
+            args.nonce_lane,
+            args.user_nonce,
+        );
         signature_data.append_bytes(FIELDS_SEPARATOR_CHAR);
-        signature_data.append(args.own_sc_address.as_managed_buffer());
+        signature_data.append(&serialized_action);
+        if let Some(extra_signed_data) = args.opt_extra_signed_data {
+            signature_data.append_bytes(FIELDS_SEPARATOR_CHAR);
+            signature_data.append(extra_signed_data);
+        }
+
+        match args.opt_session {
+            Some((session_id, session_key)) => {
+                self.check_session_sig(session_id, session_key, &signature_data, args.signatures)
+            }
+            None => self.check_sig(
+                args.user_id,
+                args.user_address,
+                &signature_data,
+                args.signatures,
+            ),
+        }
+    }
+
+    fn check_batch_signature(&self, args: CheckBatchSignatureArgs<Self::Api>) {
+        let mut serialized_actions = ManagedBuffer::new();
+        let encode_result = args.actions.top_encode(&mut serialized_actions);
+        require!(encode_result.is_ok(), "Encoding error");
+
+        let message_kind = if args.atomic {
+            MessageKind::AtomicBatch
+        } else {
+            MessageKind::Batch
+        };
+        let mut signature_data = self.new_signature_data(
+            args.own_sc_address,
+            message_kind,
+            args.user_address,
+            args.user_nonce,
+        );
+        signature_data.append_bytes(FIELDS_SEPARATOR_CHAR);
+        signature_data.append(&serialized_actions);
+
+        self.check_sig(
+            args.user_id,
+            args.user_address,
+            &signature_data,
+            args.signatures,
+        );
+    }
+
+    fn check_withdraw_signature(&self, args: CheckWithdrawSignatureArgs<Self::Api>) {
+        let mut serialized_payments = ManagedBuffer::new();
+        let encode_result = args.payments.top_encode(&mut serialized_payments);
+        require!(encode_result.is_ok(), "Encoding error");
+
+        let mut signature_data = self.new_signature_data(
+            args.own_sc_address,
+            MessageKind::Withdraw,
+            args.user_address,
+            args.user_nonce,
+        );
         signature_data.append_bytes(FIELDS_SEPARATOR_CHAR);
-        signature_data.append_bytes(&args.user_nonce.to_be_bytes());
+        signature_data.append(args.receiver.as_managed_buffer());
         signature_data.append_bytes(FIELDS_SEPARATOR_CHAR);
-        signature_data.append(&serialized_action);
+        signature_data.append(&serialized_payments);
 
-        self.check_sig(args.user_address, &signature_data, args.signature);
+        self.check_sig(
+            args.user_id,
+            args.user_address,
+            &signature_data,
+            args.signatures,
+        );
     }
 
-    #[cfg(not(debug_assertions))]
+    fn check_cancel_intent_signature(
+        &self,
+        user_id: AddressId,
+        user_address: &ManagedAddress,
+        user_nonce: Nonce,
+        intent_id: IntentId,
+        signatures: &KeySignatures<Self::Api>,
+    ) {
+        let own_sc_address = self.blockchain().get_sc_address();
+        let mut signature_data = self.new_signature_data(
+            &own_sc_address,
+            MessageKind::CancelIntent,
+            user_address,
+            user_nonce,
+        );
+        signature_data.append_bytes(FIELDS_SEPARATOR_CHAR);
+        signature_data.append_bytes(&intent_id.to_be_bytes());
+
+        self.check_sig(user_id, user_address, &signature_data, signatures);
+    }
+
+    /// Each module signs its account settings operations under its own message kind,
+    /// so a signature can't be replayed for another module, even if the encoding matches
+    fn check_operation_signature<T: TopEncode>(
+        &self,
+        user_id: AddressId,
+        user_address: &ManagedAddress,
+        user_nonce: Nonce,
+        message_kind: MessageKind,
+        operation: &T,
+        signatures: &KeySignatures<Self::Api>,
+    ) {
+        let mut serialized_operation = ManagedBuffer::new();
+        let encode_result = operation.top_encode(&mut serialized_operation);
+        require!(encode_result.is_ok(), "Encoding error");
+
+        let own_sc_address = self.blockchain().get_sc_address();
+        let mut signature_data =
+            self.new_signature_data(&own_sc_address, message_kind, user_address, user_nonce);
+        signature_data.append_bytes(FIELDS_SEPARATOR_CHAR);
+        signature_data.append(&serialized_operation);
+
+        self.check_sig(user_id, user_address, &signature_data, signatures);
+    }
+
+    /// domain_separator@user_address@nonce_lane nonce, for the default lane.
+    /// Callers append the message specific fields.
+    fn new_signature_data(
+        &self,
+        own_sc_address: &ManagedAddress,
+        message_kind: MessageKind,
+        user_address: &ManagedAddress,
+        user_nonce: Nonce,
+    ) -> ManagedBuffer {
+        self.new_lane_signature_data(
+            own_sc_address,
+            message_kind,
+            user_address,
+            DEFAULT_NONCE_LANE,
+            user_nonce,
+        )
+    }
+
+    /// The nonce field is prefixed by the lane key (8 bytes big-endian, 0 for the default lane),
+    /// so a signature can't be replayed on another lane
+    fn new_lane_signature_data(
+        &self,
+        own_sc_address: &ManagedAddress,
+        message_kind: MessageKind,
+        user_address: &ManagedAddress,
+        nonce_lane: NonceLane,
+        user_nonce: Nonce,
+    ) -> ManagedBuffer {
+        let domain_separator = DomainSeparator {
+            chain_id: self.chain_id().get(),
+            contract_address: own_sc_address.clone(),
+            schema_version: SIGNATURE_SCHEMA_VERSION,
+            message_kind,
+        };
+
+        let mut signature_data = ManagedBuffer::new();
+        let encode_result = domain_separator.top_encode(&mut signature_data);
+        require!(encode_result.is_ok(), "Encoding error");
+
+        signature_data.append_bytes(FIELDS_SEPARATOR_CHAR);
+        signature_data.append(user_address.as_managed_buffer());
+        signature_data.append_bytes(FIELDS_SEPARATOR_CHAR);
+        signature_data.append_bytes(&nonce_lane.to_be_bytes());
+        signature_data.append_bytes(&user_nonce.to_be_bytes());
+
+        signature_data
+    }
+
+    /// The signatures come from distinct keys of the user, sorted by key ID, all over the same payload,
+    /// with at least as many entries as the user's threshold
     fn check_sig(
         &self,
+        user_id: AddressId,
         user_address: &ManagedAddress,
         signature_data: &ManagedBuffer,
-        signature: &Signature<Self::Api>,
+        signatures: &KeySignatures<Self::Api>,
     ) {
-        // TODO: Update to Secp256r1 when it's available
-        self.crypto().verify_ed25519(
-            user_address.as_managed_buffer(),
-            signature_data,
-            signature.as_managed_buffer(),
+        require!(
+            signatures.len() >= self.signature_threshold_or_default(user_id) as usize,
+            "Not enough signatures"
         );
+
+        let mut opt_last_key_id = None;
+        for key_signature in signatures {
+            // sorted and unique, so no key can be counted twice
+            if let Some(last_key_id) = opt_last_key_id {
+                require!(key_signature.key_id > last_key_id, "Duplicate signing key");
+            }
+            opt_last_key_id = Some(key_signature.key_id);
+
+            let signing_key = self.get_signing_key(user_id, user_address, key_signature.key_id);
+            self.verify_key_sig(&signing_key, signature_data, &key_signature.signature);
+        }
     }
 
+    /// Session keys sign alone, under their session ID. The user's threshold does not apply.
+    fn check_session_sig(
+        &self,
+        session_id: SessionId,
+        session_key: &SigningKey<Self::Api>,
+        signature_data: &ManagedBuffer,
+        signatures: &KeySignatures<Self::Api>,
+    ) {
+        require!(signatures.len() == 1, "Invalid session signature");
+
+        let key_signature = signatures.get(0);
+        require!(key_signature.key_id == session_id, "Unknown signing key");
+
+        self.verify_key_sig(session_key, signature_data, &key_signature.signature);
+    }
+
+    /// Users registered before key sets existed only have their address key,
+    /// under the ID it gets once stored
+    fn get_signing_key(
+        &self,
+        user_id: AddressId,
+        user_address: &ManagedAddress,
+        key_id: KeyId,
+    ) -> SigningKey<Self::Api> {
+        let key_ids_mapper = self.user_key_ids(user_id);
+        if key_ids_mapper.is_empty() {
+            require!(key_id == ADDRESS_KEY_ID, "Unknown signing key");
+
+            return SigningKey::from_address(user_address);
+        }
+
+        require!(key_ids_mapper.contains(&key_id), "Unknown signing key");
+
+        self.user_key(user_id, key_id).get()
+    }
+
+    fn verify_key_sig(
+        &self,
+        signing_key: &SigningKey<Self::Api>,
+        signature_data: &ManagedBuffer,
+        signature: &Signature<Self::Api>,
+    ) {
+        if signing_key.key_type == KeyType::Ed25519 {
+            require!(
+                signature.len() == ED25519_SIGNATURE_LEN,
+                "Invalid signature length"
+            );
+        }
+
+        self.verify_crypto_sig(signing_key, signature_data, signature);
+    }
+
+    #[cfg(not(debug_assertions))]
+    fn verify_crypto_sig(
+        &self,
+        signing_key: &SigningKey<Self::Api>,
+        signature_data: &ManagedBuffer,
+        signature: &Signature<Self::Api>,
+    ) {
+        match signing_key.key_type {
+            KeyType::Ed25519 => {
+                self.crypto()
+                    .verify_ed25519(&signing_key.public_key, signature_data, signature);
+            }
+            KeyType::Secp256k1 => {
+                let is_valid = self.crypto().verify_secp256k1(
+                    &signing_key.public_key,
+                    signature_data,
+                    signature,
+                );
+                require!(is_valid, "Invalid signature");
+            }
+            // rejected by `require_valid_key_format`
+            KeyType::Secp256r1 => sc_panic!("Key type not supported"),
+        }
+    }
+
+    /// Only the cryptographic check is skipped in debug builds,
+    /// so tests don't need real signatures
     #[cfg(debug_assertions)]
-    fn check_sig(
+    fn verify_crypto_sig(
         &self,
-        _user_address: &ManagedAddress,
+        _signing_key: &SigningKey<Self::Api>,
         _signature_data: &ManagedBuffer,
         _signature: &Signature<Self::Api>,
     ) {
     }
+
+    fn insert_user_key(&self, user_id: AddressId, signing_key: SigningKey<Self::Api>) -> KeyId {
+        let mut key_ids_mapper = self.user_key_ids(user_id);
+        require!(key_ids_mapper.len() < MAX_USER_KEYS, "Too many keys");
+        self.require_valid_new_key(user_id, &signing_key);
+
+        let key_id = self.user_last_key_id(user_id).update(|last_key_id| {
+            *last_key_id += 1;
+
+            *last_key_id
+        });
+        let _ = key_ids_mapper.insert(key_id);
+        self.user_key(user_id, key_id).set(signing_key);
+
+        key_id
+    }
+
+    fn insert_address_key(&self, user_id: AddressId, user_address: &ManagedAddress) -> KeyId {
+        self.insert_user_key(user_id, SigningKey::from_address(user_address))
+    }
+
+    /// Users registered before key sets existed sign with their address key, which isn't stored.
+    /// It's stored as their first key before their key set changes, so it keeps its authority.
+    fn store_legacy_address_key(&self, user_id: AddressId, user_address: &ManagedAddress) {
+        if self.user_key_ids(user_id).is_empty() {
+            let _ = self.insert_address_key(user_id, user_address);
+        }
+    }
+
+    fn delete_user_key(&self, user_id: AddressId, key_id: KeyId) {
+        let mut key_ids_mapper = self.user_key_ids(user_id);
+        require!(key_ids_mapper.len() > 1, "Cannot remove last key");
+
+        let removed = key_ids_mapper.swap_remove(&key_id);
+        require!(removed, "Unknown signing key");
+        require!(
+            key_ids_mapper.len() >= self.signature_threshold_or_default(user_id) as usize,
+            "Threshold higher than key count"
+        );
+
+        self.user_key(user_id, key_id).clear();
+    }
+
+    /// The key ID is kept, only the public key is replaced
+    fn replace_user_key(&self, user_id: AddressId, key_id: KeyId, new_key: SigningKey<Self::Api>) {
+        require!(
+            self.user_key_ids(user_id).contains(&key_id),
+            "Unknown signing key"
+        );
+        self.require_valid_new_key(user_id, &new_key);
+
+        self.user_key(user_id, key_id).set(new_key);
+    }
+
+    /// Used by recovery, which replaces the whole key set
+    fn clear_user_keys(&self, user_id: AddressId) {
+        let mut key_ids_mapper = self.user_key_ids(user_id);
+        for key_id in key_ids_mapper.iter() {
+            self.user_key(user_id, key_id).clear();
+        }
+
+        key_ids_mapper.clear();
+        self.user_signature_threshold(user_id).clear();
+    }
+
+    fn update_signature_threshold(&self, user_id: AddressId, threshold: u32) {
+        require!(
+            threshold > 0 && threshold as usize <= self.user_key_ids(user_id).len(),
+            "Invalid threshold"
+        );
+
+        self.user_signature_threshold(user_id).set(threshold);
+    }
+
+    fn signature_threshold_or_default(&self, user_id: AddressId) -> u32 {
+        let threshold_mapper = self.user_signature_threshold(user_id);
+        if threshold_mapper.is_empty() {
+            return DEFAULT_SIGNATURE_THRESHOLD;
+        }
+
+        threshold_mapper.get()
+    }
+
+    fn require_valid_new_key(&self, user_id: AddressId, signing_key: &SigningKey<Self::Api>) {
+        self.require_valid_key_format(signing_key);
+
+        for key_id in self.user_key_ids(user_id).iter() {
+            let existing_key = self.user_key(user_id, key_id).get();
+            require!(
+                existing_key.public_key != signing_key.public_key,
+                "Key already registered"
+            );
+        }
+    }
+
+    /// Direct calls from the user address act as an address key signature,
+    /// so they're only accepted while that key can still sign alone
+    fn require_address_key_authority(&self, user_id: AddressId, user_address: &ManagedAddress) {
+        let key_ids_mapper = self.user_key_ids(user_id);
+        if key_ids_mapper.is_empty() {
+            return;
+        }
+
+        require!(
+            self.signature_threshold_or_default(user_id) == 1
+                && self.find_address_key_id(user_id, user_address).is_some(),
+            "Address key not authorized"
+        );
+    }
+
+    fn find_address_key_id(
+        &self,
+        user_id: AddressId,
+        user_address: &ManagedAddress,
+    ) -> Option<KeyId> {
+        for key_id in self.user_key_ids(user_id).iter() {
+            let signing_key = self.user_key(user_id, key_id).get();
+            if signing_key.key_type == KeyType::Ed25519
+                && &signing_key.public_key == user_address.as_managed_buffer()
+            {
+                return Some(key_id);
+            }
+        }
+
+        None
+    }
+
+    /// Checked for every key the user may sign with, including session and recovery keys
+    fn require_valid_key_format(&self, signing_key: &SigningKey<Self::Api>) {
+        require!(
+            signing_key.key_type.is_supported(),
+            "Key type not supported"
+        );
+        require!(
+            signing_key
+                .key_type
+                .is_valid_public_key_len(signing_key.public_key.len()),
+            "Invalid public key length"
+        );
+    }
+
+    #[storage_mapper("userKeyIds")]
+    fn user_key_ids(&self, user_id: AddressId) -> UnorderedSetMapper<KeyId>;
+
+    #[storage_mapper("userKey")]
+    fn user_key(
+        &self,
+        user_id: AddressId,
+        key_id: KeyId,
+    ) -> SingleValueMapper<SigningKey<Self::Api>>;
+
+    #[storage_mapper("userSignatureThreshold")]
+    fn user_signature_threshold(&self, user_id: AddressId) -> SingleValueMapper<u32>;
+
+    #[storage_mapper("userLastKeyId")]
+    fn user_last_key_id(&self, user_id: AddressId) -> SingleValueMapper<KeyId>;
+
+    #[view(getChainId)]
+    #[storage_mapper("chainId")]
+    fn chain_id(&self) -> SingleValueMapper<ManagedBuffer>;
 }
@@ -0,0 +1,279 @@
+use super::{
+    common_types::{Nonce, SpendingLimit, Timestamp},
+    signature::{KeySignatures, MessageKind},
+};
+
+multiversx_sc::imports!();
+multiversx_sc::derive_imports!();
+
+pub const LIMIT_CHANGE_TIME_LOCK: Timestamp = 86_400;
+
+/// Applied after the time-lock, or as soon as enough guardians approved it
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode)]
+pub struct PendingLimitChange<M: ManagedTypeApi> {
+    pub opt_limit: Option<SpendingLimit<M>>,
+    pub effective_after: Timestamp,
+    pub approvals: ManagedVec<M, ManagedAddress<M>>,
+}
+
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode)]
+pub enum SpendingLimitOperation<M: ManagedTypeApi> {
+    SetSpendingLimit {
+        token_id: TokenIdentifier<M>,
+        opt_limit: Option<SpendingLimit<M>>,
+    },
+}
+
+/// Stricter limits apply immediately. Raising or removing a limit is time-locked,
+/// unless the user's guardians co-sign it.
+#[multiversx_sc::module]
+pub trait SpendingLimitsModule:
+    super::users::UsersModule
+    + super::signature::SignatureModule
+    + super::sessions::SessionsModule
+    + super::recovery::RecoveryModule
+    + super::events::EventsModule
+{
+    /// Use "EGLD" as token ID for EGLD. No limit removes the current one.
+    #[endpoint(setSpendingLimit)]
+    fn set_spending_limit(
+        &self,
+        user_address: ManagedAddress,
+        token_id: TokenIdentifier,
+        opt_limit: Option<SpendingLimit<Self::Api>>,
+        user_nonce: Nonce,
+        signatures: KeySignatures<Self::Api>,
+    ) {
+        if let Some(limit) = &opt_limit {
+            require!(limit.window > 0, "Invalid window");
+        }
+
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let operation = SpendingLimitOperation::SetSpendingLimit {
+            token_id: token_id.clone(),
+            opt_limit: opt_limit.clone(),
+        };
+        self.check_spending_limit_operation(
+            user_id,
+            &user_address,
+            user_nonce,
+            &operation,
+            &signatures,
+        );
+
+        let pending_mapper = self.pending_limit_change(user_id, &token_id);
+        pending_mapper.clear();
+
+        if self.is_stricter_limit(user_id, &token_id, &opt_limit) {
+            self.apply_spending_limit(&user_address, user_id, &token_id, opt_limit);
+            return;
+        }
+
+        let current_timestamp = self.blockchain().get_block_timestamp();
+        let effective_after = current_timestamp + LIMIT_CHANGE_TIME_LOCK;
+        self.spending_limit_change_scheduled_event(
+            &user_address,
+            &token_id,
+            effective_after,
+            &opt_limit,
+        );
+        pending_mapper.set(PendingLimitChange {
+            opt_limit,
+            effective_after,
+            approvals: ManagedVec::new(),
+        });
+    }
+
+    /// Callable by anyone once the time-lock expired
+    #[endpoint(applySpendingLimitChange)]
+    fn apply_spending_limit_change(&self, user_address: ManagedAddress, token_id: TokenIdentifier) {
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let pending_mapper = self.pending_limit_change(user_id, &token_id);
+        require!(!pending_mapper.is_empty(), "No pending limit change");
+
+        let pending_change = pending_mapper.take();
+        let current_timestamp = self.blockchain().get_block_timestamp();
+        require!(
+            current_timestamp >= pending_change.effective_after,
+            "Limit change time-locked"
+        );
+
+        self.apply_spending_limit(&user_address, user_id, &token_id, pending_change.opt_limit);
+    }
+
+    #[endpoint(approveSpendingLimitChange)]
+    fn approve_spending_limit_change(
+        &self,
+        user_address: ManagedAddress,
+        token_id: TokenIdentifier,
+    ) {
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let caller = self.blockchain().get_caller();
+        self.require_guardian(user_id, &caller);
+
+        let pending_mapper = self.pending_limit_change(user_id, &token_id);
+        require!(!pending_mapper.is_empty(), "No pending limit change");
+
+        let mut pending_change = pending_mapper.get();
+        self.remove_former_guardian_approvals(user_id, &mut pending_change);
+        require!(
+            !pending_change.approvals.contains(&caller),
+            "Already approved"
+        );
+
+        pending_change.approvals.push(caller.clone());
+        self.spending_limit_change_approved_event(
+            &user_address,
+            &token_id,
+            &caller,
+            pending_change.approvals.len() as u32,
+        );
+
+        let threshold = self.recovery_config(user_id).get().threshold;
+        if pending_change.approvals.len() < threshold as usize {
+            pending_mapper.set(pending_change);
+            return;
+        }
+
+        pending_mapper.clear();
+        self.apply_spending_limit(&user_address, user_id, &token_id, pending_change.opt_limit);
+    }
+
+    /// None if the token has no limit
+    #[view(getRemainingAllowance)]
+    fn get_remaining_allowance(
+        &self,
+        user_address: ManagedAddress,
+        token_id: TokenIdentifier,
+    ) -> OptionalValue<BigUint> {
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let limit_mapper = self.spending_limit(user_id, &token_id);
+        if limit_mapper.is_empty() {
+            return OptionalValue::None;
+        }
+
+        let limit = limit_mapper.get();
+        let current_timestamp = self.blockchain().get_block_timestamp();
+        let window = self.get_current_window(
+            &self.spending_window(user_id, &token_id),
+            &limit,
+            current_timestamp,
+        );
+        let spent = window.get_spent();
+        if spent >= limit.amount {
+            return OptionalValue::Some(BigUint::zero());
+        }
+
+        OptionalValue::Some(limit.amount - spent)
+    }
+
+    #[view(getSpendingLimits)]
+    fn get_spending_limits(
+        &self,
+        user_address: ManagedAddress,
+    ) -> MultiValueEncoded<MultiValue2<TokenIdentifier, SpendingLimit<Self::Api>>> {
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let mut result = MultiValueEncoded::new();
+        for token_id in self.user_limited_tokens(user_id).iter() {
+            let limit = self.spending_limit(user_id, &token_id).get();
+            result.push((token_id, limit).into());
+        }
+
+        result
+    }
+
+    #[view(getPendingSpendingLimitChange)]
+    fn get_pending_spending_limit_change(
+        &self,
+        user_address: ManagedAddress,
+        token_id: TokenIdentifier,
+    ) -> OptionalValue<PendingLimitChange<Self::Api>> {
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let pending_mapper = self.pending_limit_change(user_id, &token_id);
+        if pending_mapper.is_empty() {
+            return OptionalValue::None;
+        }
+
+        OptionalValue::Some(pending_mapper.get())
+    }
+
+    fn check_spending_limit_operation(
+        &self,
+        user_id: AddressId,
+        user_address: &ManagedAddress,
+        user_nonce: Nonce,
+        operation: &SpendingLimitOperation<Self::Api>,
+        signatures: &KeySignatures<Self::Api>,
+    ) {
+        self.consume_user_nonce(user_id, user_nonce);
+        self.check_operation_signature(
+            user_id,
+            user_address,
+            user_nonce,
+            MessageKind::UpdateSpendingLimits,
+            operation,
+            signatures,
+        );
+    }
+
+    /// Guardians may have changed since the change was approved
+    fn remove_former_guardian_approvals(
+        &self,
+        user_id: AddressId,
+        pending_change: &mut PendingLimitChange<Self::Api>,
+    ) {
+        let guardians_mapper = self.user_guardians(user_id);
+        let mut approvals = ManagedVec::new();
+        for approver in &pending_change.approvals {
+            if guardians_mapper.contains(&approver) {
+                approvals.push(approver.clone_value());
+            }
+        }
+
+        pending_change.approvals = approvals;
+    }
+
+    fn is_stricter_limit(
+        &self,
+        user_id: AddressId,
+        token_id: &TokenIdentifier,
+        opt_new_limit: &Option<SpendingLimit<Self::Api>>,
+    ) -> bool {
+        let limit_mapper = self.spending_limit(user_id, token_id);
+        match opt_new_limit {
+            Some(new_limit) => {
+                limit_mapper.is_empty() || new_limit.is_at_least_as_strict_as(&limit_mapper.get())
+            }
+            None => limit_mapper.is_empty(),
+        }
+    }
+
+    fn apply_spending_limit(
+        &self,
+        user_address: &ManagedAddress,
+        user_id: AddressId,
+        token_id: &TokenIdentifier,
+        opt_limit: Option<SpendingLimit<Self::Api>>,
+    ) {
+        self.spending_limit_set_event(user_address, token_id, &opt_limit);
+
+        match opt_limit {
+            Some(limit) => {
+                let _ = self.user_limited_tokens(user_id).insert(token_id.clone());
+                self.spending_limit(user_id, token_id).set(limit);
+            }
+            None => {
+                let _ = self.user_limited_tokens(user_id).swap_remove(token_id);
+                self.spending_limit(user_id, token_id).clear();
+                self.spending_window(user_id, token_id).clear();
+            }
+        }
+    }
+
+    #[storage_mapper("pendingLimitChange")]
+    fn pending_limit_change(
+        &self,
+        user_id: AddressId,
+        token_id: &TokenIdentifier,
+    ) -> SingleValueMapper<PendingLimitChange<Self::Api>>;
+}
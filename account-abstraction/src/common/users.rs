@@ -1,8 +1,11 @@
 use mergeable::Mergeable;
 
 use super::{
-    common_types::{Nonce, PaymentsVec, UniquePayments, EGLD_TOKEN_ID},
-    signature::Signature,
+    common_types::{
+        Nonce, NonceLane, PaymentsVec, SpendingLimit, SpendingWindow, Timestamp, UniquePayments,
+        DEFAULT_NONCE_LANE, EGLD_TOKEN_ID,
+    },
+    signature::{CheckWithdrawSignatureArgs, KeySignatures, Signature},
 };
 
 multiversx_sc::imports!();
@@ -10,15 +13,19 @@ multiversx_sc::imports!();
 static NOT_ENOUGH_TOKENS_ERR_MSG: &[u8] = b"Not enough tokens";
 
 #[multiversx_sc::module]
-pub trait UsersModule: super::signature::SignatureModule {
+pub trait UsersModule: super::signature::SignatureModule + super::events::EventsModule {
     #[endpoint(registerUser)]
     fn register_user(&self, user_address: ManagedAddress, signature: Signature<Self::Api>) {
         self.require_not_registered(&user_address);
         self.check_register_signature(&user_address, &signature);
 
-        let _user_id = self.user_ids().insert_new(&user_address);
+        let user_id = self.user_ids().insert_new(&user_address);
         // if first nonce ever changes, uncomment this
         // self.user_nonce(user_id).set(FIRST_NONCE);
+
+        let _ = self.insert_address_key(user_id, &user_address);
+
+        self.user_registered_event(&user_address, user_id);
     }
 
     #[payable("*")]
@@ -28,6 +35,9 @@ pub trait UsersModule: super::signature::SignatureModule {
         let payments = self.get_esdt_and_egld_payments();
         require!(!payments.is_empty(), "No payments");
 
+        let caller = self.blockchain().get_caller();
+        self.user_deposit_event(&user_address, &caller, &payments);
+
         let unique_payments = UniquePayments::new_from_payments(payments);
         let mapper = self.user_tokens(user_id);
         let mut user_tokens = self.get_or_default(&mapper);
@@ -35,6 +45,52 @@ pub trait UsersModule: super::signature::SignatureModule {
         mapper.set(user_tokens);
     }
 
+    /// To withdraw EGLD, simply use "EGLD" as token ID, 0 nonce, and the needed amount
+    #[endpoint]
+    fn withdraw(
+        &self,
+        payments: PaymentsVec<Self::Api>,
+        opt_receiver: OptionalValue<ManagedAddress>,
+    ) {
+        let caller = self.blockchain().get_caller();
+        let receiver = match opt_receiver {
+            OptionalValue::Some(receiver) => receiver,
+            OptionalValue::None => caller.clone(),
+        };
+
+        let user_id = self.user_ids().get_id_non_zero(&caller);
+        self.require_address_key_authority(user_id, &caller);
+        self.withdraw_common(user_id, &caller, &receiver, payments);
+    }
+
+    /// To withdraw EGLD, simply use "EGLD" as token ID, 0 nonce, and the needed amount
+    #[endpoint(withdrawForUser)]
+    fn withdraw_for_user(
+        &self,
+        user_address: ManagedAddress,
+        receiver: ManagedAddress,
+        payments: PaymentsVec<Self::Api>,
+        user_nonce: Nonce,
+        signatures: KeySignatures<Self::Api>,
+    ) {
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        self.consume_user_nonce(user_id, user_nonce);
+
+        let own_sc_address = self.blockchain().get_sc_address();
+        let args = CheckWithdrawSignatureArgs {
+            own_sc_address: &own_sc_address,
+            user_id,
+            user_address: &user_address,
+            user_nonce,
+            receiver: &receiver,
+            payments: &payments,
+            signatures: &signatures,
+        };
+        self.check_withdraw_signature(args);
+
+        self.withdraw_common(user_id, &user_address, &receiver, payments);
+    }
+
     #[view(getUserTokens)]
     fn get_user_tokens(&self, user_address: ManagedAddress) -> PaymentsVec<Self::Api> {
         let user_id = self.user_ids().get_id_non_zero(&user_address);
@@ -44,11 +100,17 @@ pub trait UsersModule: super::signature::SignatureModule {
         user_tokens.into_payments()
     }
 
+    /// Returns the default lane's nonce if no lane is given
     #[view(getUserNonce)]
-    fn get_user_nonce(&self, user_address: ManagedAddress) -> Nonce {
+    fn get_user_nonce(
+        &self,
+        user_address: ManagedAddress,
+        opt_nonce_lane: OptionalValue<NonceLane>,
+    ) -> Nonce {
         let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let nonce_lane = opt_nonce_lane.into_option().unwrap_or(DEFAULT_NONCE_LANE);
 
-        self.user_nonce(user_id).get()
+        self.lane_nonce(user_id, nonce_lane).get()
     }
 
     fn get_or_default(
@@ -76,24 +138,153 @@ pub trait UsersModule: super::signature::SignatureModule {
         payments
     }
 
-    fn deduct_single_payment(&self, user_id: AddressId, tokens: &EsdtTokenPayment) {
-        self.user_tokens(user_id).update(|user_tokens| {
-            let deduct_result = user_tokens.deduct_payment(tokens);
-            require!(deduct_result.is_ok(), NOT_ENOUGH_TOKENS_ERR_MSG);
-        });
+    fn withdraw_common(
+        &self,
+        user_id: AddressId,
+        user_address: &ManagedAddress,
+        receiver: &ManagedAddress,
+        payments: PaymentsVec<Self::Api>,
+    ) {
+        require!(!payments.is_empty(), "No payments");
+
+        let tokens_mapper = self.user_tokens(user_id);
+        let mut user_tokens = self.get_or_default(&tokens_mapper);
+        self.deduct_payments(user_id, &payments, &mut user_tokens);
+        tokens_mapper.set(user_tokens);
+
+        self.user_withdraw_event(user_address, receiver, &payments);
+
+        self.send_esdt_and_egld_payments(receiver, payments);
     }
 
+    fn send_esdt_and_egld_payments(
+        &self,
+        receiver: &ManagedAddress,
+        payments: PaymentsVec<Self::Api>,
+    ) {
+        let egld_token_id = TokenIdentifier::from_esdt_bytes(EGLD_TOKEN_ID);
+        let mut egld_value = BigUint::zero();
+        let mut esdt_payments = PaymentsVec::new();
+        for payment in &payments {
+            if payment.token_identifier == egld_token_id {
+                egld_value += payment.amount;
+            } else {
+                esdt_payments.push(payment);
+            }
+        }
+
+        if egld_value > 0 {
+            self.tx().to(receiver).egld(egld_value).transfer();
+        }
+        if !esdt_payments.is_empty() {
+            self.tx().to(receiver).multi_esdt(esdt_payments).transfer();
+        }
+    }
+
+    /// Outflows from the user's balance also consume the user's spending allowances
     fn deduct_payments(
         &self,
+        user_id: AddressId,
         action_payments: &PaymentsVec<Self::Api>,
         user_tokens: &mut UniquePayments<Self::Api>,
     ) {
+        self.deduct_from_balance(action_payments, user_tokens);
+
+        let current_timestamp = self.blockchain().get_block_timestamp();
         for payment in action_payments {
-            let deduct_result = user_tokens.deduct_payment(&payment);
+            self.spend_allowance(user_id, &payment, current_timestamp);
+        }
+    }
+
+    fn deduct_from_balance(
+        &self,
+        payments: &PaymentsVec<Self::Api>,
+        balance: &mut UniquePayments<Self::Api>,
+    ) {
+        for payment in payments {
+            let deduct_result = balance.deduct_payment(&payment);
             require!(deduct_result.is_ok(), NOT_ENOUGH_TOKENS_ERR_MSG);
         }
     }
 
+    fn spend_allowance(
+        &self,
+        user_id: AddressId,
+        payment: &EsdtTokenPayment,
+        current_timestamp: Timestamp,
+    ) {
+        let limit_mapper = self.spending_limit(user_id, &payment.token_identifier);
+        if limit_mapper.is_empty() {
+            return;
+        }
+
+        let limit = limit_mapper.get();
+        let window_mapper = self.spending_window(user_id, &payment.token_identifier);
+        let mut window = self.get_current_window(&window_mapper, &limit, current_timestamp);
+        window.add_spent(&payment.amount, limit.window, current_timestamp);
+        require!(
+            window.get_spent() <= limit.amount,
+            "Spending limit exceeded"
+        );
+
+        window_mapper.set(window);
+    }
+
+    /// Used when outflows are refunded, e.g. for failed async calls
+    fn restore_allowance(
+        &self,
+        user_id: AddressId,
+        payments: &PaymentsVec<Self::Api>,
+        spent_at: Timestamp,
+    ) {
+        for payment in payments {
+            let window_mapper = self.spending_window(user_id, &payment.token_identifier);
+            if window_mapper.is_empty() {
+                continue;
+            }
+
+            let mut window = window_mapper.get();
+            window.restore_spent(&payment.amount, spent_at);
+            window_mapper.set(window);
+        }
+    }
+
+    fn get_current_window(
+        &self,
+        window_mapper: &SingleValueMapper<SpendingWindow<Self::Api>>,
+        limit: &SpendingLimit<Self::Api>,
+        current_timestamp: Timestamp,
+    ) -> SpendingWindow<Self::Api> {
+        if window_mapper.is_empty() {
+            return SpendingWindow::new();
+        }
+
+        let mut window = window_mapper.get();
+        window.remove_expired_buckets(limit.window, current_timestamp);
+
+        window
+    }
+
+    fn consume_user_nonce(&self, user_id: AddressId, user_nonce: Nonce) {
+        self.consume_lane_nonce(user_id, DEFAULT_NONCE_LANE, user_nonce);
+    }
+
+    fn consume_lane_nonce(&self, user_id: AddressId, nonce_lane: NonceLane, user_nonce: Nonce) {
+        let nonce_mapper = self.lane_nonce(user_id, nonce_lane);
+        require!(nonce_mapper.get() == user_nonce, "Invalid user nonce");
+
+        nonce_mapper.set(user_nonce + 1);
+    }
+
+    /// The default lane is stored as the user's original nonce
+    fn lane_nonce(&self, user_id: AddressId, nonce_lane: NonceLane) -> SingleValueMapper<Nonce> {
+        if nonce_lane == DEFAULT_NONCE_LANE {
+            return self.user_nonce(user_id);
+        }
+
+        self.user_lane_nonce(user_id, nonce_lane)
+    }
+
     fn require_not_registered(&self, user_address: &ManagedAddress) {
         require!(
             self.user_ids().get_id(user_address) == NULL_ID,
@@ -109,4 +300,28 @@ pub trait UsersModule: super::signature::SignatureModule {
 
     #[storage_mapper("userNonce")]
     fn user_nonce(&self, user_id: AddressId) -> SingleValueMapper<Nonce>;
+
+    #[storage_mapper("userLaneNonce")]
+    fn user_lane_nonce(
+        &self,
+        user_id: AddressId,
+        nonce_lane: NonceLane,
+    ) -> SingleValueMapper<Nonce>;
+
+    #[storage_mapper("spendingLimit")]
+    fn spending_limit(
+        &self,
+        user_id: AddressId,
+        token_id: &TokenIdentifier,
+    ) -> SingleValueMapper<SpendingLimit<Self::Api>>;
+
+    #[storage_mapper("spendingWindow")]
+    fn spending_window(
+        &self,
+        user_id: AddressId,
+        token_id: &TokenIdentifier,
+    ) -> SingleValueMapper<SpendingWindow<Self::Api>>;
+
+    #[storage_mapper("userLimitedTokens")]
+    fn user_limited_tokens(&self, user_id: AddressId) -> UnorderedSetMapper<TokenIdentifier>;
 }
@@ -10,16 +10,30 @@ pub mod user_actions;
 pub trait AccountAbstraction:
     common::users::UsersModule
     + common::signature::SignatureModule
+    + common::keys::KeysModule
+    + common::recovery::RecoveryModule
+    + common::sessions::SessionsModule
+    + common::spending_limits::SpendingLimitsModule
+    + common::nonces::NoncesModule
     + user_actions::execution::ExecutionModule
     + user_actions::whitelist_actions::WhitelistActionsModule
+    + user_actions::paymaster::PaymasterModule
     + user_actions::intents::IntentsModule
     + user_actions::intent_storage::IntentStorageModule
+    + user_actions::paymaster_storage::PaymasterStorageModule
+    + user_actions::whitelist_storage::WhitelistStorageModule
     + user_actions::views::ViewsModule
     + common::custom_callbacks::CustomCallbacksModule
+    + common::action_outcomes::ActionOutcomesModule
+    + common::events::EventsModule
 {
     #[init]
-    fn init(&self) {}
+    fn init(&self, chain_id: ManagedBuffer) {
+        self.set_chain_id(chain_id);
+    }
 
     #[upgrade]
-    fn upgrade(&self) {}
+    fn upgrade(&self, chain_id: ManagedBuffer) {
+        self.set_chain_id(chain_id);
+    }
 }
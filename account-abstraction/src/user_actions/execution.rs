@@ -1,11 +1,16 @@
-use crate::common::{custom_callbacks::CallbackProxy as _, signature::CheckExecutionSignatureArgs};
+use crate::common::{
+    action_outcomes::ActionRef,
+    custom_callbacks::{CallbackProxy as _, ChargedLimits},
+    sessions::SessionId,
+    signature::{CheckBatchSignatureArgs, CheckExecutionSignatureArgs, KeySignatures},
+};
 
 use crate::common::common_types::{
     Action, ActionMultiValue, ActionStruct, CallType, EgldTxType, EsdtTxType, GasLimit,
-    GeneralActionData, PaymentsVec, EGLD_TOKEN_ID,
+    GeneralActionData, Nonce, PaymentsVec, SponsoredFee, DEFAULT_NONCE_LANE, EGLD_TOKEN_ID,
 };
 
-use super::intents::IntentId;
+use super::whitelist_storage::WhitelistActionRef;
 
 const DEFAULT_EXTRA_CALLBACK_GAS: GasLimit = 10_000_000;
 static INVALID_TX_DATA_ERR_MSG: &[u8] = b"Invalid Tx data";
@@ -16,8 +21,14 @@ multiversx_sc::imports!();
 pub trait ExecutionModule:
     crate::common::users::UsersModule
     + crate::common::signature::SignatureModule
+    + crate::common::keys::KeysModule
+    + crate::common::sessions::SessionsModule
     + crate::common::custom_callbacks::CustomCallbacksModule
+    + crate::common::action_outcomes::ActionOutcomesModule
+    + crate::common::events::EventsModule
     + super::intent_storage::IntentStorageModule
+    + super::paymaster_storage::PaymasterStorageModule
+    + super::whitelist_storage::WhitelistStorageModule
 {
     #[endpoint(multiActionForUser)]
     fn multi_action_for_user(
@@ -27,7 +38,72 @@ pub trait ExecutionModule:
     ) {
         let own_sc_address = self.blockchain().get_sc_address();
         let actions_vec = self.collect_actions(actions);
-        self.multi_action_for_user_common(&user_address, &actions_vec, &own_sc_address);
+        self.multi_action_for_user_common(
+            &user_address,
+            &actions_vec,
+            &own_sc_address,
+            None,
+            None,
+            None,
+        );
+    }
+
+    /// A single signature and nonce for the whole batch of actions
+    #[endpoint(multiActionBatchForUser)]
+    fn multi_action_batch_for_user(
+        &self,
+        user_address: ManagedAddress,
+        user_nonce: Nonce,
+        signatures: KeySignatures<Self::Api>,
+        actions: MultiValueEncoded<GeneralActionData<Self::Api>>,
+    ) {
+        self.multi_action_batch_for_user_common(
+            user_address,
+            user_nonce,
+            signatures,
+            false,
+            actions,
+        );
+    }
+
+    /// Same as `multiActionBatchForUser`, but may only contain transfers and sync calls,
+    /// so any failure reverts the whole batch. Signed under its own message kind.
+    #[endpoint(atomicMultiActionBatchForUser)]
+    fn atomic_multi_action_batch_for_user(
+        &self,
+        user_address: ManagedAddress,
+        user_nonce: Nonce,
+        signatures: KeySignatures<Self::Api>,
+        actions: MultiValueEncoded<GeneralActionData<Self::Api>>,
+    ) {
+        self.multi_action_batch_for_user_common(
+            user_address,
+            user_nonce,
+            signatures,
+            true,
+            actions,
+        );
+    }
+
+    /// All actions must be signed by the session key, and must be allowed by the session.
+    /// The default nonce lane is reserved for the user's keys, so sessions use other lanes.
+    #[endpoint(sessionMultiActionForUser)]
+    fn session_multi_action_for_user(
+        &self,
+        user_address: ManagedAddress,
+        session_id: SessionId,
+        actions: MultiValueEncoded<ActionMultiValue<Self::Api>>,
+    ) {
+        let own_sc_address = self.blockchain().get_sc_address();
+        let actions_vec = self.collect_actions(actions);
+        self.multi_action_for_user_common(
+            &user_address,
+            &actions_vec,
+            &own_sc_address,
+            Some(session_id),
+            None,
+            None,
+        );
     }
 
     /// Pairs of (user_address, actions_vec)
@@ -41,7 +117,14 @@ pub trait ExecutionModule:
         let own_sc_address = self.blockchain().get_sc_address();
         for pair in args {
             let (user_address, actions_vec) = pair.into_tuple();
-            self.multi_action_for_user_common(&user_address, &actions_vec, &own_sc_address);
+            self.multi_action_for_user_common(
+                &user_address,
+                &actions_vec,
+                &own_sc_address,
+                None,
+                None,
+                None,
+            );
         }
     }
 
@@ -53,8 +136,8 @@ pub trait ExecutionModule:
 
         let mut actions_vec = ManagedVec::new();
         for action_multi in actions {
-            let (action, user_nonce, signature) = action_multi.into_tuple();
-            let action_struct = ActionStruct::new(action, user_nonce, signature);
+            let (action, nonce_lane, user_nonce, signatures) = action_multi.into_tuple();
+            let action_struct = ActionStruct::new(action, nonce_lane, user_nonce, signatures);
             actions_vec.push(action_struct);
         }
 
@@ -66,13 +149,59 @@ pub trait ExecutionModule:
         user_address: &ManagedAddress,
         actions: &ManagedVec<T>,
         own_sc_address: &ManagedAddress,
+        opt_session_id: Option<SessionId>,
+        opt_sponsored_fee: Option<&SponsoredFee<Self::Api>>,
+        opt_whitelist_action: Option<&WhitelistActionRef<Self::Api>>,
     ) {
-        self.check_can_execute_actions(user_address, actions, own_sc_address);
-
-        for action_struct in actions {
+        self.check_can_execute_actions(
+            user_address,
+            actions,
+            own_sc_address,
+            opt_session_id,
+            opt_sponsored_fee.map(|sponsored_fee| &sponsored_fee.paymaster_address),
+        );
+
+        let user_id = self.user_ids().get_id(user_address);
+        let tx_hash = self.blockchain().get_tx_hash();
+        let charged_limits = ChargedLimits {
+            spent_at: self.blockchain().get_block_timestamp(),
+            opt_session_id,
+            opt_whitelist_action: opt_whitelist_action.cloned(),
+        };
+        for (i, action_struct) in actions.iter().enumerate() {
+            let action_index = i as u32;
+            let (nonce_lane, opt_nonce) = (
+                action_struct.get_nonce_lane(),
+                action_struct.get_opt_nonce(),
+            );
             let mut action = action_struct.get_general_action_data();
+            self.action_executed_event(
+                user_address,
+                user_id,
+                nonce_lane,
+                opt_nonce,
+                action_index,
+                action.call_type,
+                &action.dest_address,
+                &action.payments,
+            );
+
+            let action_ref = ActionRef {
+                tx_hash: tx_hash.clone(),
+                action_index,
+                nonce_lane,
+                opt_nonce,
+                opt_intent_id: None,
+            };
             let egld_value = self.get_egld_value(&mut action.payments);
-            self.execute_action_by_type(user_address.clone(), egld_value, action, None);
+            self.execute_action_by_type(
+                user_address.clone(),
+                egld_value,
+                action,
+                action_ref,
+                opt_sponsored_fee.cloned(),
+                charged_limits.clone(),
+            );
         }
     }
 
@@ -81,50 +210,84 @@ pub trait ExecutionModule:
         user_address: &ManagedAddress,
         actions: &ManagedVec<T>,
         own_sc_address: &ManagedAddress,
+        opt_session_id: Option<SessionId>,
+        opt_paymaster_address: Option<&ManagedAddress>,
     ) {
         let user_id = self.user_ids().get_id_non_zero(user_address);
-        let nonce_mapper = self.user_nonce(user_id);
-        let mut user_nonce = nonce_mapper.get();
         let tokens_mapper = self.user_tokens(user_id);
         let mut user_tokens = tokens_mapper.get();
+        let opt_session = opt_session_id
+            .map(|session_id| (session_id, self.get_active_session(user_id, session_id)));
+        let mut opt_session_caps =
+            opt_session_id.map(|session_id| self.session_remaining_caps(user_id, session_id).get());
         for action_struct in actions {
-            let (opt_nonce, opt_signature, action) = (
+            let (nonce_lane, opt_nonce, opt_signatures, mut opt_extra_signed_data, action) = (
+                action_struct.get_nonce_lane(),
                 action_struct.get_opt_nonce(),
-                action_struct.get_opt_signature(),
+                action_struct.get_opt_signatures(),
+                action_struct.get_opt_extra_signed_data(),
                 action_struct.get_general_action_data(),
             );
+            // sponsored actions may only be paid for by the paymaster the user signed for
+            if let Some(paymaster_address) = opt_paymaster_address {
+                opt_extra_signed_data = Some(paymaster_address.as_managed_buffer().clone());
+            }
             require!(
                 &action.dest_address != own_sc_address,
                 "Invalid destination"
             );
             require!(!action.is_banned_endpoint_name(), "Invalid endpoint name");
+            if let Some((_, session)) = &opt_session {
+                // keeps the default lane's nonces for the user's own keys
+                require!(
+                    nonce_lane != DEFAULT_NONCE_LANE,
+                    "Default nonce lane not allowed for sessions"
+                );
+                require!(opt_signatures.is_some(), "Session signature required");
+                self.require_session_allowed_action(session, &action);
+            }
 
-            if let Some(nonce) = opt_nonce {
-                require!(nonce == user_nonce, "Invalid user nonce");
+            if let Some(user_nonce) = opt_nonce {
+                self.consume_lane_nonce(user_id, nonce_lane, user_nonce);
 
-                if let Some(signature) = opt_signature {
+                if let Some(signatures) = opt_signatures {
                     let args = CheckExecutionSignatureArgs {
                         own_sc_address,
+                        user_id,
                         user_address,
+                        nonce_lane,
                         user_nonce,
                         action: &action,
-                        signature: &signature,
+                        opt_extra_signed_data: opt_extra_signed_data.as_ref(),
+                        opt_session: opt_session
+                            .as_ref()
+                            .map(|(session_id, session)| (*session_id, &session.signing_key)),
+                        signatures: &signatures,
                     };
                     self.check_execution_signature(args);
                 }
-
-                user_nonce += 1;
             }
 
             self.check_exec_args(&action);
-            self.deduct_payments(&action.payments, &mut user_tokens);
+
+            let payments_with_fee = action.get_payments_with_fee();
+            self.deduct_payments(user_id, &payments_with_fee, &mut user_tokens);
+            if let Some(session_caps) = &mut opt_session_caps {
+                self.deduct_session_caps(&payments_with_fee, session_caps);
+            }
         }
 
-        nonce_mapper.set(user_nonce);
         tokens_mapper.set(user_tokens);
+        if let (Some(session_id), Some(session_caps)) = (opt_session_id, opt_session_caps) {
+            self.session_remaining_caps(user_id, session_id)
+                .set(session_caps);
+        }
     }
 
     fn check_exec_args(&self, action: &GeneralActionData<Self::Api>) {
+        if let Some(relayer_fee) = &action.opt_relayer_fee {
+            require!(relayer_fee.payment.amount > 0, "Invalid relayer fee");
+        }
         if !self.blockchain().is_smart_contract(&action.dest_address) {
             require!(
                 action.opt_execution.is_none(),
@@ -190,8 +353,12 @@ pub trait ExecutionModule:
         user_address: ManagedAddress,
         egld_value: BigUint,
         action: GeneralActionData<Self::Api>,
-        opt_intent_id: Option<IntentId>,
+        action_ref: ActionRef<Self::Api>,
+        opt_sponsored_fee: Option<SponsoredFee<Self::Api>>,
+        charged_limits: ChargedLimits<Self::Api>,
     ) {
+        let relayer = self.blockchain().get_caller();
+        let opt_relayer_fee = action.opt_relayer_fee.clone();
         match action.call_type {
             CallType::Transfer => {
                 if egld_value == 0 {
@@ -205,24 +372,46 @@ pub trait ExecutionModule:
                         .egld(egld_value)
                         .transfer()
                 }
+
+                self.pay_relayer_fee(&relayer, opt_relayer_fee);
+                self.pay_sponsored_fee(&relayer, &user_address, opt_sponsored_fee);
             }
             CallType::Sync => {
-                if egld_value == 0 {
+                let min_returns = action.min_returns.clone();
+                let previous_back_transfers = self.blockchain().get_back_transfers();
+                let back_transfers = if egld_value == 0 {
                     let tx = self.build_esdt_tx(action);
-                    tx.sync_call();
+                    tx.returns(ReturnsBackTransfers).sync_call()
                 } else {
                     let tx = self.build_egld_tx(egld_value, action);
-                    tx.sync_call();
-                }
+                    tx.returns(ReturnsBackTransfers).sync_call()
+                };
+
+                let returned_payments =
+                    self.get_back_transfers_payments(previous_back_transfers, back_transfers);
+                require!(
+                    self.are_min_returns_met(&min_returns, &returned_payments),
+                    "Min returns not met"
+                );
+
+                self.add_user_funds(&user_address, &returned_payments);
+                self.pay_relayer_fee(&relayer, opt_relayer_fee);
+                self.pay_sponsored_fee(&relayer, &user_address, opt_sponsored_fee);
             }
             CallType::Async => {
                 let mut original_payments = action.payments.clone();
+                let min_returns = action.min_returns.clone();
                 if egld_value == 0 {
                     let tx = self.build_esdt_tx(action);
                     tx.with_callback(self.callbacks().user_action_cb(
                         user_address,
                         original_payments,
-                        opt_intent_id,
+                        charged_limits,
+                        action_ref,
+                        min_returns,
+                        relayer,
+                        opt_relayer_fee,
+                        opt_sponsored_fee,
                     ))
                     .with_extra_gas_for_callback(DEFAULT_EXTRA_CALLBACK_GAS)
                     .register_promise();
@@ -237,7 +426,12 @@ pub trait ExecutionModule:
                     tx.with_callback(self.callbacks().user_action_cb(
                         user_address,
                         original_payments,
-                        opt_intent_id,
+                        charged_limits,
+                        action_ref,
+                        min_returns,
+                        relayer,
+                        opt_relayer_fee,
+                        opt_sponsored_fee,
                     ))
                     .with_extra_gas_for_callback(DEFAULT_EXTRA_CALLBACK_GAS)
                     .register_promise();
@@ -246,6 +440,82 @@ pub trait ExecutionModule:
         };
     }
 
+    /// Back-transfers accumulate over the whole transaction,
+    /// so only the ones received after `previous_back_transfers` belong to the last call
+    fn get_back_transfers_payments(
+        &self,
+        previous_back_transfers: BackTransfers<Self::Api>,
+        back_transfers: BackTransfers<Self::Api>,
+    ) -> PaymentsVec<Self::Api> {
+        let opt_new_payments = back_transfers.esdt_payments.slice(
+            previous_back_transfers.esdt_payments.len(),
+            back_transfers.esdt_payments.len(),
+        );
+        require!(opt_new_payments.is_some(), "Invalid back transfers");
+
+        let mut payments = unsafe { opt_new_payments.unwrap_unchecked() };
+        let egld_amount =
+            back_transfers.total_egld_amount - previous_back_transfers.total_egld_amount;
+        if egld_amount > 0 {
+            payments.push(EsdtTokenPayment::new(
+                TokenIdentifier::from_esdt_bytes(EGLD_TOKEN_ID),
+                0,
+                egld_amount,
+            ));
+        }
+
+        payments
+    }
+
+    fn multi_action_batch_for_user_common(
+        &self,
+        user_address: ManagedAddress,
+        user_nonce: Nonce,
+        signatures: KeySignatures<Self::Api>,
+        atomic: bool,
+        actions: MultiValueEncoded<GeneralActionData<Self::Api>>,
+    ) {
+        self.require_non_empty_actions(&actions);
+
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        self.consume_user_nonce(user_id, user_nonce);
+
+        let own_sc_address = self.blockchain().get_sc_address();
+        let actions_vec = actions.to_vec();
+        if atomic {
+            self.require_sync_only_actions(&actions_vec);
+        }
+
+        let args = CheckBatchSignatureArgs {
+            own_sc_address: &own_sc_address,
+            user_id,
+            user_address: &user_address,
+            user_nonce,
+            actions: &actions_vec,
+            atomic,
+            signatures: &signatures,
+        };
+        self.check_batch_signature(args);
+
+        self.multi_action_for_user_common(
+            &user_address,
+            &actions_vec,
+            &own_sc_address,
+            None,
+            None,
+            None,
+        );
+    }
+
+    fn require_sync_only_actions(&self, actions: &ManagedVec<GeneralActionData<Self::Api>>) {
+        for action in actions {
+            require!(
+                !matches!(action.call_type, CallType::Async),
+                "Async actions not allowed in atomic batch"
+            );
+        }
+    }
+
     fn require_non_empty_actions<T>(&self, actions: &MultiValueEncoded<T>) {
         require!(!actions.is_empty(), "No actions");
     }
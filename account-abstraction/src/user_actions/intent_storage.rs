@@ -4,6 +4,11 @@ multiversx_sc::imports!();
 
 #[multiversx_sc::module]
 pub trait IntentStorageModule {
+    fn remove_intent(&self, user_id: AddressId, intent_id: IntentId) {
+        let _ = self.all_user_intents(user_id).swap_remove(&intent_id);
+        self.user_intent(user_id, intent_id).clear();
+    }
+
     #[storage_mapper("allUserIntents")]
     fn all_user_intents(&self, user_id: AddressId) -> UnorderedSetMapper<IntentId>;
 
@@ -1,8 +1,16 @@
-use crate::common::common_types::{
-    Action, ActionMultiValue, ActionStruct, CallType, GeneralActionData,
+use crate::common::{
+    action_outcomes::ActionRef,
+    common_types::{
+        Action, CallType, GeneralActionData, Nonce, NonceLane, ScExecutionData, Timestamp,
+        DEFAULT_NONCE_LANE,
+    },
+    custom_callbacks::ChargedLimits,
+    signature::KeySignatures,
 };
 
 pub type IntentId = u64;
+pub type IntentMultiValue<M> =
+    MultiValue4<GeneralActionData<M>, IntentConditions<M>, Nonce, KeySignatures<M>>;
 
 #[derive(TypeAbi, TopEncode, TopDecode, NestedDecode, NestedEncode)]
 pub enum IntentType {
@@ -10,19 +18,199 @@ pub enum IntentType {
     InProgress,
 }
 
-#[derive(TypeAbi, TopEncode, TopDecode, NestedDecode, NestedEncode)]
+#[derive(
+    TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode, Clone, Copy, ManagedVecItem,
+)]
+pub enum Comparator {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+impl Comparator {
+    pub fn compare<M: ManagedTypeApi>(&self, actual: &BigUint<M>, expected: &BigUint<M>) -> bool {
+        match self {
+            Comparator::Less => actual < expected,
+            Comparator::LessOrEqual => actual <= expected,
+            Comparator::Equal => actual == expected,
+            Comparator::GreaterOrEqual => actual >= expected,
+            Comparator::Greater => actual > expected,
+        }
+    }
+}
+
+/// The first value returned by the view is interpreted as a big-endian unsigned number
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode, ManagedVecItem)]
+pub struct IntentPredicate<M: ManagedTypeApi> {
+    pub sc_address: ManagedAddress<M>,
+    pub view_data: ScExecutionData<M>,
+    pub comparator: Comparator,
+    pub expected_value: BigUint<M>,
+}
+
+/// Compared against the user's balance in the contract, which excludes funds reserved by intents.
+/// Use "EGLD" as token ID for EGLD.
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode, ManagedVecItem)]
+pub struct BalancePredicate<M: ManagedTypeApi> {
+    pub token_id: TokenIdentifier<M>,
+    pub token_nonce: u64,
+    pub comparator: Comparator,
+    pub expected_value: BigUint<M>,
+}
+
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode, ManagedVecItem)]
+pub struct IntentConditions<M: ManagedTypeApi> {
+    pub opt_valid_after: Option<Timestamp>,
+    pub opt_valid_until: Option<Timestamp>,
+    pub opt_predicate: Option<IntentPredicate<M>>,
+    pub opt_balance_predicate: Option<BalancePredicate<M>>,
+}
+
+impl<M: ManagedTypeApi> Default for IntentConditions<M> {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            opt_valid_after: None,
+            opt_valid_until: None,
+            opt_predicate: None,
+            opt_balance_predicate: None,
+        }
+    }
+}
+
+impl<M: ManagedTypeApi> IntentConditions<M> {
+    #[inline]
+    pub fn is_expired(&self, current_timestamp: Timestamp) -> bool {
+        matches!(self.opt_valid_until, Some(valid_until) if current_timestamp > valid_until)
+    }
+
+    #[inline]
+    pub fn is_not_yet_valid(&self, current_timestamp: Timestamp) -> bool {
+        matches!(self.opt_valid_after, Some(valid_after) if current_timestamp < valid_after)
+    }
+}
+
+/// Intents stored before conditions existed end after the intent data,
+/// and are decoded without conditions. Intents stored before their save time was kept have none.
+#[derive(TypeAbi, TopEncode, NestedEncode)]
 pub struct Intent<M: ManagedTypeApi> {
     pub intent_type: IntentType,
     pub intent_data: GeneralActionData<M>,
+    pub conditions: IntentConditions<M>,
+    pub opt_saved_at: Option<Timestamp>,
+}
+
+impl<M: ManagedTypeApi> NestedDecode for Intent<M> {
+    fn dep_decode_or_handle_err<I, H>(input: &mut I, h: H) -> Result<Self, H::HandledErr>
+    where
+        I: codec::NestedDecodeInput,
+        H: codec::DecodeErrorHandler,
+    {
+        let intent_type = IntentType::dep_decode_or_handle_err(input, h)?;
+        let intent_data = GeneralActionData::dep_decode_or_handle_err(input, h)?;
+        let conditions = if input.is_depleted() {
+            IntentConditions::default()
+        } else {
+            IntentConditions::dep_decode_or_handle_err(input, h)?
+        };
+        let opt_saved_at = if input.is_depleted() {
+            None
+        } else {
+            Option::<Timestamp>::dep_decode_or_handle_err(input, h)?
+        };
+
+        Result::Ok(Self::new(
+            intent_type,
+            intent_data,
+            conditions,
+            opt_saved_at,
+        ))
+    }
+}
+
+impl<M: ManagedTypeApi> TopDecode for Intent<M> {
+    fn top_decode_or_handle_err<I, H>(input: I, h: H) -> Result<Self, H::HandledErr>
+    where
+        I: codec::TopDecodeInput,
+        H: codec::DecodeErrorHandler,
+    {
+        codec::top_decode_from_nested_or_handle_err(input, h)
+    }
 }
 
 impl<M: ManagedTypeApi> Intent<M> {
     #[inline]
-    pub fn new(intent_type: IntentType, intent_data: GeneralActionData<M>) -> Self {
+    pub fn new(
+        intent_type: IntentType,
+        intent_data: GeneralActionData<M>,
+        conditions: IntentConditions<M>,
+        opt_saved_at: Option<Timestamp>,
+    ) -> Self {
         Self {
             intent_type,
             intent_data,
+            conditions,
+            opt_saved_at,
+        }
+    }
+}
+
+/// Intents always use the default nonce lane
+#[derive(TypeAbi, TopEncode, TopDecode, NestedDecode, NestedEncode, ManagedVecItem)]
+pub struct IntentActionStruct<M: ManagedTypeApi> {
+    pub action: GeneralActionData<M>,
+    pub conditions: IntentConditions<M>,
+    pub user_nonce: Nonce,
+    pub signatures: KeySignatures<M>,
+}
+
+impl<M: ManagedTypeApi> IntentActionStruct<M> {
+    #[inline]
+    pub fn new(
+        action: GeneralActionData<M>,
+        conditions: IntentConditions<M>,
+        user_nonce: Nonce,
+        signatures: KeySignatures<M>,
+    ) -> Self {
+        Self {
+            action,
+            conditions,
+            user_nonce,
+            signatures,
+        }
+    }
+}
+
+impl<M: ManagedTypeApi> Action<M> for IntentActionStruct<M> {
+    fn get_general_action_data(self) -> GeneralActionData<M> {
+        self.action
+    }
+
+    fn get_nonce_lane(&self) -> NonceLane {
+        DEFAULT_NONCE_LANE
+    }
+
+    fn get_opt_nonce(&self) -> Option<Nonce> {
+        Some(self.user_nonce)
+    }
+
+    fn get_opt_signatures(&self) -> Option<KeySignatures<M>> {
+        Some(self.signatures.clone())
+    }
+
+    fn get_opt_extra_signed_data(&self) -> Option<ManagedBuffer<M>> {
+        let mut serialized_conditions = ManagedBuffer::new();
+        if self
+            .conditions
+            .top_encode(&mut serialized_conditions)
+            .is_err()
+        {
+            M::error_api_impl().signal_error(b"Encoding error");
         }
+
+        Some(serialized_conditions)
     }
 }
 
@@ -33,18 +221,24 @@ multiversx_sc::derive_imports!();
 pub trait IntentsModule:
     crate::common::users::UsersModule
     + crate::common::signature::SignatureModule
+    + crate::common::keys::KeysModule
+    + crate::common::sessions::SessionsModule
     + crate::common::custom_callbacks::CustomCallbacksModule
+    + crate::common::action_outcomes::ActionOutcomesModule
+    + crate::common::events::EventsModule
     + super::execution::ExecutionModule
     + super::intent_storage::IntentStorageModule
+    + super::paymaster_storage::PaymasterStorageModule
+    + super::whitelist_storage::WhitelistStorageModule
 {
     #[endpoint(saveIntents)]
     fn save_intents(
         &self,
         user_address: ManagedAddress,
-        actions: MultiValueEncoded<ActionMultiValue<Self::Api>>,
+        actions: MultiValueEncoded<IntentMultiValue<Self::Api>>,
     ) {
         let own_sc_address = self.blockchain().get_sc_address();
-        let actions_vec = self.collect_actions(actions);
+        let actions_vec = self.collect_intent_actions(actions);
         self.save_intents_common(&user_address, &actions_vec, &own_sc_address);
     }
 
@@ -52,7 +246,9 @@ pub trait IntentsModule:
     #[endpoint(multiUserSaveIntents)]
     fn multi_user_save_intents(
         &self,
-        args: MultiValueEncoded<MultiValue2<ManagedAddress, ManagedVec<ActionStruct<Self::Api>>>>,
+        args: MultiValueEncoded<
+            MultiValue2<ManagedAddress, ManagedVec<IntentActionStruct<Self::Api>>>,
+        >,
     ) {
         self.require_non_empty_actions(&args);
 
@@ -75,36 +271,250 @@ pub trait IntentsModule:
             "Intent execution already in progress"
         );
 
-        let egld_value = self.get_egld_value(&mut intent.intent_data.payments);
+        let current_timestamp = self.blockchain().get_block_timestamp();
+        require!(
+            !intent.conditions.is_not_yet_valid(current_timestamp),
+            "Intent not yet valid"
+        );
+        require!(
+            !intent.conditions.is_expired(current_timestamp),
+            "Intent expired"
+        );
+
+        if let Some(predicate) = &intent.conditions.opt_predicate {
+            require!(self.is_predicate_met(predicate), "Intent condition not met");
+        }
+        if let Some(balance_predicate) = &intent.conditions.opt_balance_predicate {
+            require!(
+                self.is_balance_predicate_met(user_id, balance_predicate),
+                "Intent condition not met"
+            );
+        }
+
+        // locked until the callback either removes the intent or reverts the state
+        intent.intent_type = IntentType::InProgress;
+        intent_mapper.set(&intent);
+
+        let mut intent_data = intent.intent_data;
+        self.intent_executed_event(
+            &user_address,
+            intent_id,
+            &intent_data.dest_address,
+            &intent_data.payments,
+        );
+
+        let action_ref = ActionRef {
+            tx_hash: self.blockchain().get_tx_hash(),
+            action_index: 0,
+            nonce_lane: DEFAULT_NONCE_LANE,
+            opt_nonce: None,
+            opt_intent_id: Some(intent_id),
+        };
+        let egld_value = self.get_egld_value(&mut intent_data.payments);
+        let charged_limits = ChargedLimits {
+            spent_at: self.blockchain().get_block_timestamp(),
+            opt_session_id: None,
+            opt_whitelist_action: None,
+        };
         self.execute_action_by_type(
-            user_address.clone(),
+            user_address,
             egld_value,
-            intent.intent_data,
-            Some(intent_id),
+            intent_data,
+            action_ref,
+            None,
+            charged_limits,
+        );
+    }
+
+    /// The user may cancel directly while the address key can sign alone,
+    /// otherwise a (nonce, signature) pair is required
+    #[endpoint(cancelIntent)]
+    fn cancel_intent(
+        &self,
+        user_address: ManagedAddress,
+        intent_id: IntentId,
+        opt_signatures: OptionalValue<MultiValue2<Nonce, KeySignatures<Self::Api>>>,
+    ) {
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let caller = self.blockchain().get_caller();
+        if caller != user_address {
+            let (user_nonce, signatures) = match opt_signatures {
+                OptionalValue::Some(multi_value) => multi_value.into_tuple(),
+                OptionalValue::None => sc_panic!("Signature required"),
+            };
+            self.consume_user_nonce(user_id, user_nonce);
+            self.check_cancel_intent_signature(
+                user_id,
+                &user_address,
+                user_nonce,
+                intent_id,
+                &signatures,
+            );
+        } else {
+            self.require_address_key_authority(user_id, &user_address);
+        }
+
+        let intent_mapper = self.user_intent(user_id, intent_id);
+        require!(!intent_mapper.is_empty(), "Intent doesn't exist");
+
+        let intent = intent_mapper.get();
+        require!(
+            matches!(intent.intent_type, IntentType::AwaitingExecution),
+            "Intent execution already in progress"
         );
+
+        self.remove_intent(user_id, intent_id);
+        self.refund_intent(user_id, &user_address, &intent);
+
+        self.intent_cancelled_event(&user_address, intent_id);
+    }
+
+    /// Anyone may remove expired intents, the reserved funds are refunded to the user
+    #[endpoint(cleanupExpiredIntents)]
+    fn cleanup_expired_intents(
+        &self,
+        user_address: ManagedAddress,
+        intent_ids: MultiValueEncoded<IntentId>,
+    ) {
+        require!(!intent_ids.is_empty(), "No intents");
+
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let current_timestamp = self.blockchain().get_block_timestamp();
+        for intent_id in intent_ids {
+            let intent_mapper = self.user_intent(user_id, intent_id);
+            require!(!intent_mapper.is_empty(), "Intent doesn't exist");
+
+            let intent = intent_mapper.get();
+            require!(
+                matches!(intent.intent_type, IntentType::AwaitingExecution),
+                "Intent execution already in progress"
+            );
+            require!(
+                intent.conditions.is_expired(current_timestamp),
+                "Intent not expired"
+            );
+
+            self.remove_intent(user_id, intent_id);
+            self.refund_intent(user_id, &user_address, &intent);
+
+            self.intent_expired_event(&user_address, intent_id);
+        }
+    }
+
+    /// The reserved funds never left the account, so they stop counting towards the spending limits
+    fn refund_intent(
+        &self,
+        user_id: AddressId,
+        user_address: &ManagedAddress,
+        intent: &Intent<Self::Api>,
+    ) {
+        let refund_payments = intent.intent_data.get_payments_with_fee();
+        if let Some(saved_at) = intent.opt_saved_at {
+            self.restore_allowance(user_id, &refund_payments, saved_at);
+        }
+
+        self.refund_user(user_address, &refund_payments);
+    }
+
+    fn is_predicate_met(&self, predicate: &IntentPredicate<Self::Api>) -> bool {
+        let view_data = &predicate.view_data;
+        let results = self.send_raw().execute_on_dest_context_readonly_raw(
+            view_data.gas_limit,
+            &predicate.sc_address,
+            &view_data.endpoint_name,
+            &view_data.args.clone().into(),
+        );
+        require!(!results.is_empty(), "No view result");
+
+        let actual_value = BigUint::from_bytes_be_buffer(&results.get(0));
+
+        predicate
+            .comparator
+            .compare(&actual_value, &predicate.expected_value)
+    }
+
+    fn is_balance_predicate_met(
+        &self,
+        user_id: AddressId,
+        predicate: &BalancePredicate<Self::Api>,
+    ) -> bool {
+        let user_tokens = self.get_or_default(&self.user_tokens(user_id));
+        let actual_value = user_tokens
+            .into_payments()
+            .iter()
+            .find(|payment| {
+                payment.token_identifier == predicate.token_id
+                    && payment.token_nonce == predicate.token_nonce
+            })
+            .map(|payment| payment.amount)
+            .unwrap_or_else(BigUint::zero);
+
+        predicate
+            .comparator
+            .compare(&actual_value, &predicate.expected_value)
+    }
+
+    fn collect_intent_actions(
+        &self,
+        actions: MultiValueEncoded<IntentMultiValue<Self::Api>>,
+    ) -> ManagedVec<IntentActionStruct<Self::Api>> {
+        self.require_non_empty_actions(&actions);
+
+        let mut actions_vec = ManagedVec::new();
+        for action_multi in actions {
+            let (action, conditions, user_nonce, signatures) = action_multi.into_tuple();
+            let action_struct = IntentActionStruct::new(action, conditions, user_nonce, signatures);
+            actions_vec.push(action_struct);
+        }
+
+        actions_vec
     }
 
     fn save_intents_common(
         &self,
         user_address: &ManagedAddress,
-        actions: &ManagedVec<ActionStruct<Self::Api>>,
+        actions: &ManagedVec<IntentActionStruct<Self::Api>>,
         own_sc_address: &ManagedAddress,
     ) {
-        self.check_can_execute_actions(user_address, actions, own_sc_address);
+        self.check_can_execute_actions(user_address, actions, own_sc_address, None, None);
 
         let user_id = self.user_ids().get_id(user_address);
+        let current_timestamp = self.blockchain().get_block_timestamp();
         let mut intent_id = self.last_intent_id().get() + 1;
         let mut all_intents_mapper = self.all_user_intents(user_id);
         for action_struct in actions {
-            let action = action_struct.get_general_action_data();
+            let (action, conditions) = (action_struct.action, action_struct.conditions);
             require!(
                 matches!(action.call_type, CallType::Async),
                 "Only async call supported"
             );
+            require!(!conditions.is_expired(current_timestamp), "Intent expired");
+            if let (Some(valid_after), Some(valid_until)) =
+                (conditions.opt_valid_after, conditions.opt_valid_until)
+            {
+                require!(valid_after <= valid_until, "Invalid validity window");
+            }
+            if let Some(predicate) = &conditions.opt_predicate {
+                require!(
+                    self.blockchain().is_smart_contract(&predicate.sc_address),
+                    "Invalid predicate address"
+                );
+            }
+
+            self.intent_saved_event(
+                user_address,
+                intent_id,
+                &action.dest_address,
+                &action.payments,
+            );
 
             let _ = all_intents_mapper.insert(intent_id);
-            self.user_intent(user_id, intent_id)
-                .set(Intent::new(IntentType::AwaitingExecution, action));
+            self.user_intent(user_id, intent_id).set(Intent::new(
+                IntentType::AwaitingExecution,
+                action,
+                conditions,
+                Some(current_timestamp),
+            ));
 
             intent_id += 1;
         }
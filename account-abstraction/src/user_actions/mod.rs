@@ -1,5 +1,8 @@
 pub mod execution;
 pub mod intent_storage;
 pub mod intents;
+pub mod paymaster;
+pub mod paymaster_storage;
 pub mod views;
 pub mod whitelist_actions;
+pub mod whitelist_storage;
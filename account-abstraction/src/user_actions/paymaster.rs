@@ -0,0 +1,293 @@
+use mergeable::Mergeable;
+
+use crate::common::common_types::{
+    ActionMultiValue, GeneralActionData, PaymentsVec, SponsoredFee, Timestamp, UniquePayments,
+};
+
+use super::{paymaster_storage::PeriodUsage, whitelist_actions::WhitelistAction};
+
+multiversx_sc::imports!();
+multiversx_sc::derive_imports!();
+
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode)]
+pub struct PaymasterPolicy<M: ManagedTypeApi> {
+    pub fee_per_action: EsdtTokenPayment<M>,
+    pub user_cap_per_period: BigUint<M>,
+    pub total_cap_per_period: BigUint<M>,
+    pub period_duration: Timestamp,
+}
+
+#[multiversx_sc::module]
+pub trait PaymasterModule:
+    crate::common::users::UsersModule
+    + crate::common::signature::SignatureModule
+    + crate::common::keys::KeysModule
+    + crate::common::sessions::SessionsModule
+    + crate::common::custom_callbacks::CustomCallbacksModule
+    + crate::common::action_outcomes::ActionOutcomesModule
+    + crate::common::events::EventsModule
+    + super::execution::ExecutionModule
+    + super::intent_storage::IntentStorageModule
+    + super::paymaster_storage::PaymasterStorageModule
+    + super::whitelist_storage::WhitelistStorageModule
+{
+    #[payable("*")]
+    #[endpoint(depositPaymasterBudget)]
+    fn deposit_paymaster_budget(&self) {
+        let payments = self.get_esdt_and_egld_payments();
+        require!(!payments.is_empty(), "No payments");
+
+        let caller = self.blockchain().get_caller();
+        let paymaster_id = self.paymaster_ids().get_id_or_insert(&caller);
+        self.paymaster_budget_deposit_event(&caller, &payments);
+
+        let unique_payments = UniquePayments::new_from_payments(payments);
+        let mapper = self.paymaster_budget(paymaster_id);
+        let mut budget = self.get_or_default(&mapper);
+        budget.merge_with(unique_payments);
+        mapper.set(budget);
+    }
+
+    #[endpoint(withdrawPaymasterBudget)]
+    fn withdraw_paymaster_budget(&self, payments: PaymentsVec<Self::Api>) {
+        require!(!payments.is_empty(), "No payments");
+
+        let caller = self.blockchain().get_caller();
+        let paymaster_id = self.paymaster_ids().get_id_non_zero(&caller);
+        let mapper = self.paymaster_budget(paymaster_id);
+        let mut budget = self.get_or_default(&mapper);
+        self.deduct_from_balance(&payments, &mut budget);
+        mapper.set(budget);
+
+        self.paymaster_budget_withdraw_event(&caller, &payments);
+        self.send_esdt_and_egld_payments(&caller, payments);
+    }
+
+    /// The fee is paid to the relayer for each sponsored action.
+    /// Caps are expressed in the fee token and reset every period.
+    #[endpoint(setPaymasterPolicy)]
+    fn set_paymaster_policy(
+        &self,
+        fee_per_action: EsdtTokenPayment,
+        user_cap_per_period: BigUint,
+        total_cap_per_period: BigUint,
+        period_duration: Timestamp,
+    ) {
+        require!(fee_per_action.amount > 0, "Invalid fee");
+        require!(period_duration > 0, "Invalid period duration");
+
+        let caller = self.blockchain().get_caller();
+        let paymaster_id = self.paymaster_ids().get_id_or_insert(&caller);
+        let policy = PaymasterPolicy {
+            fee_per_action,
+            user_cap_per_period,
+            total_cap_per_period,
+            period_duration,
+        };
+        self.paymaster_policy_set_event(&caller, &policy);
+        self.paymaster_policy(paymaster_id).set(policy);
+    }
+
+    /// Pairs of (SC address, endpoint name)
+    #[endpoint(addSponsoredActions)]
+    fn add_sponsored_actions(
+        &self,
+        action_types: MultiValueEncoded<MultiValue2<ManagedAddress, ManagedBuffer>>,
+    ) {
+        require!(!action_types.is_empty(), "No sponsored actions");
+
+        let caller = self.blockchain().get_caller();
+        let paymaster_id = self.paymaster_ids().get_id_or_insert(&caller);
+        let mut sponsored_mapper = self.sponsored_actions(paymaster_id);
+        let mut added_action_types = ManagedVec::new();
+        for multi_value in action_types {
+            let (sc_address, endpoint_name) = multi_value.into_tuple();
+            let action_type = WhitelistAction::new(sc_address, endpoint_name);
+            let _ = sponsored_mapper.insert(action_type.clone());
+            added_action_types.push(action_type);
+        }
+
+        self.sponsored_actions_added_event(&caller, &added_action_types);
+    }
+
+    /// Pairs of (SC address, endpoint name)
+    #[endpoint(removeSponsoredActions)]
+    fn remove_sponsored_actions(
+        &self,
+        action_types: MultiValueEncoded<MultiValue2<ManagedAddress, ManagedBuffer>>,
+    ) {
+        require!(!action_types.is_empty(), "No sponsored actions");
+
+        let caller = self.blockchain().get_caller();
+        let paymaster_id = self.paymaster_ids().get_id_non_zero(&caller);
+        let mut sponsored_mapper = self.sponsored_actions(paymaster_id);
+        let mut removed_action_types = ManagedVec::new();
+        for multi_value in action_types {
+            let (sc_address, endpoint_name) = multi_value.into_tuple();
+            let action_type = WhitelistAction::new(sc_address, endpoint_name);
+            let removed = sponsored_mapper.swap_remove(&action_type);
+            require!(removed, "Action not sponsored");
+
+            removed_action_types.push(action_type);
+        }
+
+        self.sponsored_actions_removed_event(&caller, &removed_action_types);
+    }
+
+    /// The relayer is reimbursed by the paymaster instead of the user.
+    /// Fees for async actions are reserved from the budget, and only paid once the action succeeds.
+    /// Users sign the paymaster's address along with each action.
+    #[endpoint(sponsoredMultiActionForUser)]
+    fn sponsored_multi_action_for_user(
+        &self,
+        paymaster_address: ManagedAddress,
+        user_address: ManagedAddress,
+        actions: MultiValueEncoded<ActionMultiValue<Self::Api>>,
+    ) {
+        let paymaster_id = self.paymaster_ids().get_id_non_zero(&paymaster_address);
+        let policy_mapper = self.paymaster_policy(paymaster_id);
+        require!(!policy_mapper.is_empty(), "No paymaster policy");
+
+        let policy = policy_mapper.get();
+        let actions_vec = self.collect_actions(actions);
+        let mut total_fee = BigUint::zero();
+        for action_struct in &actions_vec {
+            self.require_sponsored_action(paymaster_id, &action_struct.action);
+            total_fee += &policy.fee_per_action.amount;
+        }
+
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let current_period = self.blockchain().get_block_timestamp() / policy.period_duration;
+        self.add_period_usage(
+            self.paymaster_user_usage(paymaster_id, user_id),
+            current_period,
+            &total_fee,
+            &policy.user_cap_per_period,
+        );
+        self.add_period_usage(
+            self.paymaster_total_usage(paymaster_id),
+            current_period,
+            &total_fee,
+            &policy.total_cap_per_period,
+        );
+
+        let total_fee_payment = EsdtTokenPayment::new(
+            policy.fee_per_action.token_identifier.clone(),
+            policy.fee_per_action.token_nonce,
+            total_fee,
+        );
+        self.paymaster_budget(paymaster_id).update(|budget| {
+            let deduct_result = budget.deduct_payment(&total_fee_payment);
+            require!(deduct_result.is_ok(), "Not enough paymaster budget");
+        });
+
+        let sponsored_fee = SponsoredFee {
+            paymaster_address,
+            payment: policy.fee_per_action,
+            period: current_period,
+        };
+        let own_sc_address = self.blockchain().get_sc_address();
+        self.multi_action_for_user_common(
+            &user_address,
+            &actions_vec,
+            &own_sc_address,
+            None,
+            Some(&sponsored_fee),
+            None,
+        );
+    }
+
+    #[view(getPaymasterBudget)]
+    fn get_paymaster_budget(&self, paymaster_address: ManagedAddress) -> PaymentsVec<Self::Api> {
+        let paymaster_id = self.paymaster_ids().get_id_non_zero(&paymaster_address);
+        let mapper = self.paymaster_budget(paymaster_id);
+        let budget = self.get_or_default(&mapper);
+
+        budget.into_payments()
+    }
+
+    #[view(getPaymasterPolicy)]
+    fn get_paymaster_policy(
+        &self,
+        paymaster_address: ManagedAddress,
+    ) -> PaymasterPolicy<Self::Api> {
+        let paymaster_id = self.paymaster_ids().get_id_non_zero(&paymaster_address);
+        self.paymaster_policy(paymaster_id).get()
+    }
+
+    #[view(getSponsoredActions)]
+    fn get_sponsored_actions(
+        &self,
+        paymaster_address: ManagedAddress,
+    ) -> MultiValueEncoded<WhitelistAction<Self::Api>> {
+        let paymaster_id = self.paymaster_ids().get_id_non_zero(&paymaster_address);
+        let mut result = MultiValueEncoded::new();
+        for action_type in self.sponsored_actions(paymaster_id).iter() {
+            result.push(action_type);
+        }
+
+        result
+    }
+
+    fn require_sponsored_action(
+        &self,
+        paymaster_id: AddressId,
+        action: &GeneralActionData<Self::Api>,
+    ) {
+        require!(
+            action.opt_relayer_fee.is_none(),
+            "Relayer fee not allowed for sponsored actions"
+        );
+
+        let endpoint_name = match &action.opt_execution {
+            Some(execution) => execution.endpoint_name.clone(),
+            None => sc_panic!("Only SC calls may be sponsored"),
+        };
+        require!(
+            self.sponsored_actions(paymaster_id)
+                .contains(&WhitelistAction::new(
+                    action.dest_address.clone(),
+                    endpoint_name
+                )),
+            "Action not sponsored"
+        );
+    }
+
+    fn add_period_usage(
+        &self,
+        usage_mapper: SingleValueMapper<PeriodUsage<Self::Api>>,
+        current_period: u64,
+        amount: &BigUint,
+        cap: &BigUint,
+    ) {
+        let mut usage = if !usage_mapper.is_empty() {
+            usage_mapper.get()
+        } else {
+            PeriodUsage {
+                period: current_period,
+                amount: BigUint::zero(),
+            }
+        };
+        if usage.period != current_period {
+            usage.period = current_period;
+            usage.amount = BigUint::zero();
+        }
+
+        usage.amount += amount;
+        require!(&usage.amount <= cap, "Paymaster cap exceeded");
+
+        usage_mapper.set(usage);
+    }
+
+    #[storage_mapper("paymasterPolicy")]
+    fn paymaster_policy(
+        &self,
+        paymaster_id: AddressId,
+    ) -> SingleValueMapper<PaymasterPolicy<Self::Api>>;
+
+    #[storage_mapper("sponsoredActions")]
+    fn sponsored_actions(
+        &self,
+        paymaster_id: AddressId,
+    ) -> UnorderedSetMapper<WhitelistAction<Self::Api>>;
+}
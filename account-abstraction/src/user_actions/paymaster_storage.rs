@@ -0,0 +1,67 @@
+use crate::common::common_types::{SponsoredFee, UniquePayments};
+
+multiversx_sc::imports!();
+multiversx_sc::derive_imports!();
+
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode)]
+pub struct PeriodUsage<M: ManagedTypeApi> {
+    pub period: u64,
+    pub amount: BigUint<M>,
+}
+
+#[multiversx_sc::module]
+pub trait PaymasterStorageModule {
+    fn refund_paymaster_budget(&self, sponsored_fee: &SponsoredFee<Self::Api>) {
+        let paymaster_id = self
+            .paymaster_ids()
+            .get_id_non_zero(&sponsored_fee.paymaster_address);
+        self.paymaster_budget(paymaster_id).update(|budget| {
+            budget.add_payment(sponsored_fee.payment.clone());
+        });
+    }
+
+    /// Usage of past periods was already reset, so only the fee's own period is reduced
+    fn release_paymaster_usage(&self, user_id: AddressId, sponsored_fee: &SponsoredFee<Self::Api>) {
+        let paymaster_id = self
+            .paymaster_ids()
+            .get_id_non_zero(&sponsored_fee.paymaster_address);
+        for usage_mapper in [
+            self.paymaster_user_usage(paymaster_id, user_id),
+            self.paymaster_total_usage(paymaster_id),
+        ] {
+            if usage_mapper.is_empty() {
+                continue;
+            }
+
+            let mut usage = usage_mapper.get();
+            if usage.period != sponsored_fee.period {
+                continue;
+            }
+
+            usage.amount -= &sponsored_fee.payment.amount;
+            usage_mapper.set(usage);
+        }
+    }
+
+    #[storage_mapper("paymasterIds")]
+    fn paymaster_ids(&self) -> AddressToIdMapper<Self::Api>;
+
+    #[storage_mapper("paymasterBudget")]
+    fn paymaster_budget(
+        &self,
+        paymaster_id: AddressId,
+    ) -> SingleValueMapper<UniquePayments<Self::Api>>;
+
+    #[storage_mapper("paymasterTotalUsage")]
+    fn paymaster_total_usage(
+        &self,
+        paymaster_id: AddressId,
+    ) -> SingleValueMapper<PeriodUsage<Self::Api>>;
+
+    #[storage_mapper("paymasterUserUsage")]
+    fn paymaster_user_usage(
+        &self,
+        paymaster_id: AddressId,
+        user_id: AddressId,
+    ) -> SingleValueMapper<PeriodUsage<Self::Api>>;
+}
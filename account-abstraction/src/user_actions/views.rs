@@ -1,6 +1,6 @@
 use super::{
     intents::{Intent, IntentId},
-    whitelist_actions::WhitelistAction,
+    whitelist_actions::{ArgConstraints, WhitelistAction, WhitelistAllowance, WhitelistLimits},
 };
 
 multiversx_sc::imports!();
@@ -10,11 +10,18 @@ pub trait ViewsModule:
     super::whitelist_actions::WhitelistActionsModule
     + crate::common::users::UsersModule
     + crate::common::signature::SignatureModule
+    + crate::common::keys::KeysModule
+    + crate::common::sessions::SessionsModule
     + crate::common::custom_callbacks::CustomCallbacksModule
+    + crate::common::action_outcomes::ActionOutcomesModule
+    + crate::common::events::EventsModule
     + super::execution::ExecutionModule
     + super::intents::IntentsModule
     + super::intent_storage::IntentStorageModule
+    + super::paymaster_storage::PaymasterStorageModule
+    + super::whitelist_storage::WhitelistStorageModule
 {
+    /// Users whose entries all expired are not included, even if not pruned yet
     #[view(getAllWhitelistedUsers)]
     fn get_all_whitelisted_users(
         &self,
@@ -23,7 +30,20 @@ pub trait ViewsModule:
         let mut result = MultiValueEncoded::new();
         let whitelist_id = self.whitelist_ids().get_id_non_zero(&whitelist_address);
         let user_id_mapper = self.user_ids();
+        let current_timestamp = self.blockchain().get_block_timestamp();
         for user_id in self.all_users_for_whitelist(whitelist_id).iter() {
+            let has_active_entries =
+                self.user_whitelist(user_id, whitelist_id)
+                    .iter()
+                    .any(|action_type| {
+                        !self
+                            .whitelist_limits_or_default(user_id, whitelist_id, &action_type)
+                            .is_expired(current_timestamp)
+                    });
+            if !has_active_entries {
+                continue;
+            }
+
             let opt_user_address = user_id_mapper.get_address(user_id);
             require!(opt_user_address.is_some(), "Invalid config");
 
@@ -43,11 +63,18 @@ pub trait ViewsModule:
         let mut result = MultiValueEncoded::new();
         let whitelist_id = self.whitelist_ids().get_id_non_zero(&whitelist_address);
         let user_id_mapper = self.user_ids();
+        let current_timestamp = self.blockchain().get_block_timestamp();
         for user in users {
             let user_id = user_id_mapper.get_id_non_zero(&user);
 
             let mut whitelist_types = ManagedVec::new();
             for whitelist_type in self.user_whitelist(user_id, whitelist_id).iter() {
+                let limits =
+                    self.whitelist_limits_or_default(user_id, whitelist_id, &whitelist_type);
+                if limits.is_expired(current_timestamp) {
+                    continue;
+                }
+
                 whitelist_types.push(whitelist_type);
             }
             result.push(whitelist_types);
@@ -56,6 +83,53 @@ pub trait ViewsModule:
         result
     }
 
+    #[view(getWhitelistLimits)]
+    fn get_whitelist_limits(
+        &self,
+        user_address: ManagedAddress,
+        whitelist_address: ManagedAddress,
+        sc_address: ManagedAddress,
+        endpoint_name: ManagedBuffer,
+    ) -> WhitelistLimits {
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let whitelist_id = self.whitelist_ids().get_id_non_zero(&whitelist_address);
+        let action_type = WhitelistAction::new(sc_address, endpoint_name);
+
+        self.whitelist_limits_or_default(user_id, whitelist_id, &action_type)
+    }
+
+    #[view(getWhitelistAllowance)]
+    fn get_whitelist_allowance(
+        &self,
+        user_address: ManagedAddress,
+        whitelist_address: ManagedAddress,
+        sc_address: ManagedAddress,
+        endpoint_name: ManagedBuffer,
+    ) -> WhitelistAllowance<Self::Api> {
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let whitelist_id = self.whitelist_ids().get_id_non_zero(&whitelist_address);
+        let action_type = WhitelistAction::new(sc_address, endpoint_name);
+
+        self.whitelist_allowance(user_id, whitelist_id, &action_type)
+            .get()
+    }
+
+    #[view(getWhitelistArgConstraints)]
+    fn get_whitelist_arg_constraints(
+        &self,
+        user_address: ManagedAddress,
+        whitelist_address: ManagedAddress,
+        sc_address: ManagedAddress,
+        endpoint_name: ManagedBuffer,
+    ) -> ArgConstraints<Self::Api> {
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let whitelist_id = self.whitelist_ids().get_id_non_zero(&whitelist_address);
+        let action_type = WhitelistAction::new(sc_address, endpoint_name);
+
+        self.whitelist_arg_constraints(user_id, whitelist_id, &action_type)
+            .get()
+    }
+
     #[view(getAllUserIntentIds)]
     fn get_all_user_intent_ids(&self, user_address: ManagedAddress) -> MultiValueEncoded<IntentId> {
         let user_id = self.user_ids().get_id_non_zero(&user_address);
@@ -1,13 +1,15 @@
 use crate::common::common_types::{
-    CallType, GasLimit, GeneralActionData, PaymentsVec, ScExecutionData,
+    CallType, GasLimit, GeneralActionData, PaymentsVec, ScExecutionData, Timestamp,
 };
 
+use super::whitelist_storage::WhitelistActionRef;
+
 multiversx_sc::imports!();
 multiversx_sc::derive_imports!();
 
 const GAS_TO_SAVE: GasLimit = 100_000;
 
-#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode, ManagedVecItem)]
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode, ManagedVecItem, Clone)]
 pub struct WhitelistAction<M: ManagedTypeApi> {
     pub sc_address: ManagedAddress<M>,
     pub endpoint_name: ManagedBuffer<M>,
@@ -23,35 +25,138 @@ impl<M: ManagedTypeApi> WhitelistAction<M> {
     }
 }
 
+/// Similar to ERC-20 allowances, the budget decreases with each use
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode, ManagedVecItem, Clone)]
+pub struct TokenAllowance<M: ManagedTypeApi> {
+    pub token_id: TokenIdentifier<M>,
+    pub max_per_call: BigUint<M>,
+    pub remaining_budget: BigUint<M>,
+}
+
+pub type WhitelistAllowance<M> = ManagedVec<M, TokenAllowance<M>>;
+
+#[derive(
+    TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode, Clone, Copy, ManagedVecItem,
+)]
+pub enum ArgConstraintType {
+    /// Single value
+    Exact,
+    /// Any of the values
+    OneOf,
+    /// Big-endian min and max values, both inclusive
+    Range,
+    /// No values, the argument must be the user's address
+    UserAddress,
+}
+
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode, ManagedVecItem, Clone)]
+pub struct ArgConstraint<M: ManagedTypeApi> {
+    pub arg_index: u32,
+    pub constraint_type: ArgConstraintType,
+    pub values: ManagedVec<M, ManagedBuffer<M>>,
+}
+
+pub type ArgConstraints<M> = ManagedVec<M, ArgConstraint<M>>;
+
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode, Clone, Copy)]
+pub struct RateLimit {
+    pub max_calls: u64,
+    pub period: Timestamp,
+}
+
+/// Expired entries are treated as not whitelisted, and are pruned lazily
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode, Clone, Copy, Default)]
+pub struct WhitelistLimits {
+    pub opt_expires_at: Option<Timestamp>,
+    pub opt_rate_limit: Option<RateLimit>,
+}
+
+impl WhitelistLimits {
+    #[inline]
+    pub fn is_expired(&self, current_timestamp: Timestamp) -> bool {
+        match self.opt_expires_at {
+            Some(expires_at) => current_timestamp >= expires_at,
+            None => false,
+        }
+    }
+}
+
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode, Default)]
+pub struct CallUsage {
+    pub period: u64,
+    pub calls: u64,
+}
+
 #[multiversx_sc::module]
 pub trait WhitelistActionsModule:
     crate::common::users::UsersModule
     + crate::common::signature::SignatureModule
+    + crate::common::keys::KeysModule
+    + crate::common::sessions::SessionsModule
     + crate::common::custom_callbacks::CustomCallbacksModule
+    + crate::common::action_outcomes::ActionOutcomesModule
+    + crate::common::events::EventsModule
     + super::execution::ExecutionModule
     + super::intent_storage::IntentStorageModule
+    + super::paymaster_storage::PaymasterStorageModule
+    + super::whitelist_storage::WhitelistStorageModule
 {
-    /// Pairs of (SC address, endpoint name)
+    /// Tuples of (SC address, endpoint name, token allowances, limits, argument constraints).
+    /// Only tokens in the allowance may be spent through the action.
+    /// Arguments without constraints may take any value.
+    /// Whitelisting an action again replaces its allowance and limits.
     #[endpoint]
     fn whitelist(
         &self,
         whitelist_address: ManagedAddress,
-        action_types: MultiValueEncoded<MultiValue2<ManagedAddress, ManagedBuffer>>,
+        action_types: MultiValueEncoded<
+            MultiValue5<
+                ManagedAddress,
+                ManagedBuffer,
+                WhitelistAllowance<Self::Api>,
+                WhitelistLimits,
+                ArgConstraints<Self::Api>,
+            >,
+        >,
     ) {
         self.require_non_empty_action_types(&action_types);
 
         let caller = self.blockchain().get_caller();
         let caller_id = self.user_ids().get_id_non_zero(&caller);
+        self.require_address_key_authority(caller_id, &caller);
         let whitelist_address_id = self.whitelist_ids().get_id_or_insert(&whitelist_address);
+        let expired_action_types =
+            self.remove_expired_whitelist_entries(caller_id, whitelist_address_id);
+        self.emit_whitelist_removed_event(&caller, &whitelist_address, &expired_action_types);
+
+        let current_timestamp = self.blockchain().get_block_timestamp();
         let mut whitelist_mapper = self.user_whitelist(caller_id, whitelist_address_id);
+        let mut whitelisted_action_types = ManagedVec::new();
         for multi_value in action_types {
-            let (sc_address, endpoint_name) = multi_value.into_tuple();
-            let _ = whitelist_mapper.insert(WhitelistAction::new(sc_address, endpoint_name));
+            let (sc_address, endpoint_name, allowance, limits, arg_constraints) =
+                multi_value.into_tuple();
+            self.require_valid_allowance(&allowance);
+            self.require_valid_limits(&limits, current_timestamp);
+            self.require_valid_arg_constraints(&arg_constraints);
+
+            let action_type = WhitelistAction::new(sc_address, endpoint_name);
+            self.whitelist_allowance(caller_id, whitelist_address_id, &action_type)
+                .set(allowance);
+            self.whitelist_limits(caller_id, whitelist_address_id, &action_type)
+                .set(limits);
+            self.whitelist_arg_constraints(caller_id, whitelist_address_id, &action_type)
+                .set(arg_constraints);
+            self.whitelist_call_usage(caller_id, whitelist_address_id, &action_type)
+                .clear();
+            let _ = whitelist_mapper.insert(action_type.clone());
+            whitelisted_action_types.push(action_type);
         }
 
         let _ = self
             .all_users_for_whitelist(whitelist_address_id)
             .insert(caller_id);
+
+        self.whitelist_set_event(&caller, &whitelist_address, &whitelisted_action_types);
     }
 
     /// Pairs of (SC address, endpoint name)
@@ -65,23 +170,41 @@ pub trait WhitelistActionsModule:
 
         let caller = self.blockchain().get_caller();
         let caller_id = self.user_ids().get_id_non_zero(&caller);
+        self.require_address_key_authority(caller_id, &caller);
         let whitelist_address_id = self.whitelist_ids().get_id_non_zero(&whitelist_address);
         let mut whitelist_mapper = self.user_whitelist(caller_id, whitelist_address_id);
+        let mut removed_action_types = ManagedVec::new();
         for multi_value in action_types {
             let (sc_address, endpoint_name) = multi_value.into_tuple();
-            let removed =
-                whitelist_mapper.swap_remove(&WhitelistAction::new(sc_address, endpoint_name));
+            let action_type = WhitelistAction::new(sc_address, endpoint_name);
+            let removed = whitelist_mapper.swap_remove(&action_type);
             require!(removed, "Address not whitelisted for action");
-        }
 
-        if whitelist_mapper.is_empty() {
-            let _ = self
-                .all_users_for_whitelist(whitelist_address_id)
-                .swap_remove(&caller_id);
+            self.clear_whitelist_entry(caller_id, whitelist_address_id, &action_type);
+            removed_action_types.push(action_type);
         }
+
+        removed_action_types
+            .append_vec(self.remove_expired_whitelist_entries(caller_id, whitelist_address_id));
+        self.emit_whitelist_removed_event(&caller, &whitelist_address, &removed_action_types);
+    }
+
+    /// Callable by anyone, removes the user's expired entries for the whitelisted address
+    #[endpoint(pruneExpiredWhitelist)]
+    fn prune_expired_whitelist(
+        &self,
+        user_address: ManagedAddress,
+        whitelist_address: ManagedAddress,
+    ) {
+        let user_id = self.user_ids().get_id_non_zero(&user_address);
+        let whitelist_address_id = self.whitelist_ids().get_id_non_zero(&whitelist_address);
+        let expired_action_types =
+            self.remove_expired_whitelist_entries(user_id, whitelist_address_id);
+        self.emit_whitelist_removed_event(&user_address, &whitelist_address, &expired_action_types);
     }
 
-    /// To pass EGLD payment, simply use "EGLD" as token ID, 0 nonce, and the needed amount
+    /// To pass EGLD payment, simply use "EGLD" as token ID, 0 nonce, and the needed amount.
+    /// The caller's expired entries for the user are removed on the way.
     #[endpoint(takeAction)]
     fn take_action(
         &self,
@@ -94,17 +217,30 @@ pub trait WhitelistActionsModule:
         let user_id = self.user_ids().get_id_non_zero(&user_address);
         let caller = self.blockchain().get_caller();
         let caller_id = self.whitelist_ids().get_id_non_zero(&caller);
+        let expired_action_types = self.remove_expired_whitelist_entries(user_id, caller_id);
+        self.emit_whitelist_removed_event(&user_address, &caller, &expired_action_types);
+
+        let action_type = WhitelistAction::new(sc_address.clone(), endpoint_name.clone());
+        let current_timestamp = self.blockchain().get_block_timestamp();
         require!(
-            self.user_whitelist(user_id, caller_id)
-                .contains(&WhitelistAction::new(
-                    sc_address.clone(),
-                    endpoint_name.clone()
-                )),
+            self.is_whitelisted(user_id, caller_id, &action_type, current_timestamp),
             "Not whitelisted for action"
         );
+        self.require_arg_constraints_met(
+            user_id,
+            caller_id,
+            &action_type,
+            &user_address,
+            &endpoint_args,
+        );
+        self.add_whitelist_call(user_id, caller_id, &action_type, current_timestamp);
 
         let action_payments = match opt_user_tokens {
-            OptionalValue::Some(user_tokens) => PaymentsVec::from_single_item(user_tokens),
+            OptionalValue::Some(user_tokens) => {
+                self.spend_whitelist_allowance(user_id, caller_id, &action_type, &user_tokens);
+
+                PaymentsVec::from_single_item(user_tokens)
+            }
             OptionalValue::None => PaymentsVec::new(),
         };
 
@@ -118,13 +254,261 @@ pub trait WhitelistActionsModule:
                 args: endpoint_args,
                 gas_limit,
             }),
+            min_returns: PaymentsVec::new(),
+            opt_relayer_fee: None,
+        };
+        let whitelist_action = WhitelistActionRef {
+            whitelisted_address_id: caller_id,
+            action_type,
         };
         let own_sc_address = self.blockchain().get_sc_address();
         self.multi_action_for_user_common(
             &user_address,
             &ManagedVec::from_single_item(action_data),
             &own_sc_address,
+            None,
+            None,
+            Some(&whitelist_action),
+        );
+    }
+
+    fn spend_whitelist_allowance(
+        &self,
+        user_id: AddressId,
+        whitelisted_address_id: AddressId,
+        action_type: &WhitelistAction<Self::Api>,
+        payment: &EsdtTokenPayment,
+    ) {
+        let allowance_mapper =
+            self.whitelist_allowance(user_id, whitelisted_address_id, action_type);
+        // entries whitelisted before allowances existed must be whitelisted again to spend tokens
+        require!(!allowance_mapper.is_empty(), "Whitelist allowance not set");
+
+        let mut allowance = allowance_mapper.get();
+        let opt_index = allowance
+            .iter()
+            .position(|token_allowance| token_allowance.token_id == payment.token_identifier);
+        let index = match opt_index {
+            Some(index) => index,
+            None => sc_panic!("Token not allowed for action"),
+        };
+
+        let mut token_allowance = allowance.get(index);
+        require!(
+            payment.amount <= token_allowance.max_per_call,
+            "Amount exceeds per call limit"
+        );
+        require!(
+            payment.amount <= token_allowance.remaining_budget,
+            "Allowance exceeded"
         );
+
+        token_allowance.remaining_budget -= &payment.amount;
+        let _ = allowance.set(index, &token_allowance);
+        allowance_mapper.set(allowance);
+    }
+
+    fn require_arg_constraints_met(
+        &self,
+        user_id: AddressId,
+        whitelisted_address_id: AddressId,
+        action_type: &WhitelistAction<Self::Api>,
+        user_address: &ManagedAddress,
+        endpoint_args: &ManagedVec<ManagedBuffer>,
+    ) {
+        let arg_constraints = self
+            .whitelist_arg_constraints(user_id, whitelisted_address_id, action_type)
+            .get();
+        for arg_constraint in &arg_constraints {
+            let arg_index = arg_constraint.arg_index as usize;
+            require!(
+                arg_index < endpoint_args.len(),
+                "Missing constrained argument"
+            );
+
+            let arg = endpoint_args.get(arg_index);
+            let values = &arg_constraint.values;
+            let is_met = match arg_constraint.constraint_type {
+                ArgConstraintType::Exact => *arg == *values.get(0),
+                ArgConstraintType::OneOf => values.iter().any(|value| *value == *arg),
+                ArgConstraintType::Range => {
+                    let arg_value = BigUint::from_bytes_be_buffer(&arg);
+                    let min_value = BigUint::from_bytes_be_buffer(&values.get(0));
+                    let max_value = BigUint::from_bytes_be_buffer(&values.get(1));
+
+                    arg_value >= min_value && arg_value <= max_value
+                }
+                ArgConstraintType::UserAddress => *arg == *user_address.as_managed_buffer(),
+            };
+            require!(is_met, "Argument constraint not met");
+        }
+    }
+
+    fn is_whitelisted(
+        &self,
+        user_id: AddressId,
+        whitelisted_address_id: AddressId,
+        action_type: &WhitelistAction<Self::Api>,
+        current_timestamp: Timestamp,
+    ) -> bool {
+        if !self
+            .user_whitelist(user_id, whitelisted_address_id)
+            .contains(action_type)
+        {
+            return false;
+        }
+
+        let limits = self.whitelist_limits_or_default(user_id, whitelisted_address_id, action_type);
+
+        !limits.is_expired(current_timestamp)
+    }
+
+    fn add_whitelist_call(
+        &self,
+        user_id: AddressId,
+        whitelisted_address_id: AddressId,
+        action_type: &WhitelistAction<Self::Api>,
+        current_timestamp: Timestamp,
+    ) {
+        let limits = self.whitelist_limits_or_default(user_id, whitelisted_address_id, action_type);
+        let rate_limit = match limits.opt_rate_limit {
+            Some(rate_limit) => rate_limit,
+            None => return,
+        };
+
+        let current_period = current_timestamp / rate_limit.period;
+        let usage_mapper = self.whitelist_call_usage(user_id, whitelisted_address_id, action_type);
+        let mut usage = if !usage_mapper.is_empty() {
+            usage_mapper.get()
+        } else {
+            CallUsage::default()
+        };
+        if usage.period != current_period {
+            usage.period = current_period;
+            usage.calls = 0;
+        }
+
+        usage.calls += 1;
+        require!(usage.calls <= rate_limit.max_calls, "Rate limit exceeded");
+
+        usage_mapper.set(usage);
+    }
+
+    /// Returns the removed action types
+    fn remove_expired_whitelist_entries(
+        &self,
+        user_id: AddressId,
+        whitelisted_address_id: AddressId,
+    ) -> ManagedVec<WhitelistAction<Self::Api>> {
+        let current_timestamp = self.blockchain().get_block_timestamp();
+        let mut whitelist_mapper = self.user_whitelist(user_id, whitelisted_address_id);
+        let mut expired_action_types = ManagedVec::new();
+        for action_type in whitelist_mapper.iter() {
+            let limits =
+                self.whitelist_limits_or_default(user_id, whitelisted_address_id, &action_type);
+            if limits.is_expired(current_timestamp) {
+                expired_action_types.push(action_type);
+            }
+        }
+
+        for action_type in &expired_action_types {
+            let _ = whitelist_mapper.swap_remove(&action_type);
+            self.clear_whitelist_entry(user_id, whitelisted_address_id, &action_type);
+        }
+
+        if whitelist_mapper.is_empty() {
+            let _ = self
+                .all_users_for_whitelist(whitelisted_address_id)
+                .swap_remove(&user_id);
+        }
+
+        expired_action_types
+    }
+
+    fn emit_whitelist_removed_event(
+        &self,
+        user_address: &ManagedAddress,
+        whitelist_address: &ManagedAddress,
+        action_types: &ManagedVec<WhitelistAction<Self::Api>>,
+    ) {
+        if !action_types.is_empty() {
+            self.whitelist_removed_event(user_address, whitelist_address, action_types);
+        }
+    }
+
+    /// Entries whitelisted before limits existed have none
+    fn whitelist_limits_or_default(
+        &self,
+        user_id: AddressId,
+        whitelisted_address_id: AddressId,
+        action_type: &WhitelistAction<Self::Api>,
+    ) -> WhitelistLimits {
+        let limits_mapper = self.whitelist_limits(user_id, whitelisted_address_id, action_type);
+        if limits_mapper.is_empty() {
+            return WhitelistLimits::default();
+        }
+
+        limits_mapper.get()
+    }
+
+    fn clear_whitelist_entry(
+        &self,
+        user_id: AddressId,
+        whitelisted_address_id: AddressId,
+        action_type: &WhitelistAction<Self::Api>,
+    ) {
+        self.whitelist_allowance(user_id, whitelisted_address_id, action_type)
+            .clear();
+        self.whitelist_limits(user_id, whitelisted_address_id, action_type)
+            .clear();
+        self.whitelist_arg_constraints(user_id, whitelisted_address_id, action_type)
+            .clear();
+        self.whitelist_call_usage(user_id, whitelisted_address_id, action_type)
+            .clear();
+    }
+
+    fn require_valid_limits(&self, limits: &WhitelistLimits, current_timestamp: Timestamp) {
+        require!(!limits.is_expired(current_timestamp), "Invalid expiry");
+
+        if let Some(rate_limit) = &limits.opt_rate_limit {
+            require!(
+                rate_limit.max_calls > 0 && rate_limit.period > 0,
+                "Invalid rate limit"
+            );
+        }
+    }
+
+    fn require_valid_allowance(&self, allowance: &WhitelistAllowance<Self::Api>) {
+        for (i, token_allowance) in allowance.iter().enumerate() {
+            require!(
+                token_allowance.max_per_call > 0,
+                "Invalid max amount per call"
+            );
+
+            for other_allowance in allowance.iter().skip(i + 1) {
+                require!(
+                    other_allowance.token_id != token_allowance.token_id,
+                    "Duplicate token allowance"
+                );
+            }
+        }
+    }
+
+    fn require_valid_arg_constraints(&self, arg_constraints: &ArgConstraints<Self::Api>) {
+        for arg_constraint in arg_constraints {
+            let values = &arg_constraint.values;
+            let is_valid = match arg_constraint.constraint_type {
+                ArgConstraintType::Exact => values.len() == 1,
+                ArgConstraintType::OneOf => !values.is_empty(),
+                ArgConstraintType::Range => {
+                    values.len() == 2
+                        && BigUint::from_bytes_be_buffer(&values.get(0))
+                            <= BigUint::from_bytes_be_buffer(&values.get(1))
+                }
+                ArgConstraintType::UserAddress => values.is_empty(),
+            };
+            require!(is_valid, "Invalid argument constraint");
+        }
     }
 
     fn get_gas_for_promise(&self) -> GasLimit {
@@ -138,15 +522,29 @@ pub trait WhitelistActionsModule:
         require!(!action_types.is_empty(), "No whitelist actions");
     }
 
-    #[storage_mapper("whitelistIds")]
-    fn whitelist_ids(&self) -> AddressToIdMapper<Self::Api>;
+    #[storage_mapper("whitelistLimits")]
+    fn whitelist_limits(
+        &self,
+        user_id: AddressId,
+        whitelisted_address_id: AddressId,
+        action_type: &WhitelistAction<Self::Api>,
+    ) -> SingleValueMapper<WhitelistLimits>;
+
+    #[storage_mapper("whitelistArgConstraints")]
+    fn whitelist_arg_constraints(
+        &self,
+        user_id: AddressId,
+        whitelisted_address_id: AddressId,
+        action_type: &WhitelistAction<Self::Api>,
+    ) -> SingleValueMapper<ArgConstraints<Self::Api>>;
 
-    #[storage_mapper("userWhitelist")]
-    fn user_whitelist(
+    #[storage_mapper("whitelistCallUsage")]
+    fn whitelist_call_usage(
         &self,
         user_id: AddressId,
         whitelisted_address_id: AddressId,
-    ) -> UnorderedSetMapper<WhitelistAction<Self::Api>>;
+        action_type: &WhitelistAction<Self::Api>,
+    ) -> SingleValueMapper<CallUsage>;
 
     #[storage_mapper("allUsersForWhitelist")]
     fn all_users_for_whitelist(
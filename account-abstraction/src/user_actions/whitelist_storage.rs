@@ -0,0 +1,65 @@
+use crate::common::common_types::PaymentsVec;
+
+use super::whitelist_actions::{WhitelistAction, WhitelistAllowance};
+
+multiversx_sc::imports!();
+multiversx_sc::derive_imports!();
+
+/// The user's whitelist entry an action was taken through
+#[derive(TypeAbi, TopEncode, TopDecode, NestedEncode, NestedDecode, Clone)]
+pub struct WhitelistActionRef<M: ManagedTypeApi> {
+    pub whitelisted_address_id: AddressId,
+    pub action_type: WhitelistAction<M>,
+}
+
+#[multiversx_sc::module]
+pub trait WhitelistStorageModule {
+    /// Used when the action's payments are refunded. Removed entries are not restored.
+    fn restore_whitelist_allowance(
+        &self,
+        user_id: AddressId,
+        action_ref: &WhitelistActionRef<Self::Api>,
+        payments: &PaymentsVec<Self::Api>,
+    ) {
+        let allowance_mapper = self.whitelist_allowance(
+            user_id,
+            action_ref.whitelisted_address_id,
+            &action_ref.action_type,
+        );
+        if allowance_mapper.is_empty() {
+            return;
+        }
+
+        let mut allowance = allowance_mapper.get();
+        for payment in payments {
+            let opt_index = allowance
+                .iter()
+                .position(|token_allowance| token_allowance.token_id == payment.token_identifier);
+            if let Some(index) = opt_index {
+                let mut token_allowance = allowance.get(index);
+                token_allowance.remaining_budget += &payment.amount;
+                let _ = allowance.set(index, &token_allowance);
+            }
+        }
+
+        allowance_mapper.set(allowance);
+    }
+
+    #[storage_mapper("whitelistIds")]
+    fn whitelist_ids(&self) -> AddressToIdMapper<Self::Api>;
+
+    #[storage_mapper("userWhitelist")]
+    fn user_whitelist(
+        &self,
+        user_id: AddressId,
+        whitelisted_address_id: AddressId,
+    ) -> UnorderedSetMapper<WhitelistAction<Self::Api>>;
+
+    #[storage_mapper("whitelistAllowance")]
+    fn whitelist_allowance(
+        &self,
+        user_id: AddressId,
+        whitelisted_address_id: AddressId,
+        action_type: &WhitelistAction<Self::Api>,
+    ) -> SingleValueMapper<WhitelistAllowance<Self::Api>>;
+}
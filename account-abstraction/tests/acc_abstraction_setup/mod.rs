@@ -1,18 +1,19 @@
 use account_abstraction::{
     common::{
         common_types::{PaymentsVec, EGLD_TOKEN_ID},
-        signature::Signature,
+        signature::{KeyId, KeySignature, KeySignatures, Signature, ADDRESS_KEY_ID},
         users::UsersModule,
     },
     AccountAbstraction,
 };
-use multiversx_sc::types::{Address, EsdtTokenPayment};
+use multiversx_sc::types::{Address, EsdtTokenPayment, ManagedBuffer};
 use multiversx_sc_scenario::{
     imports::{BlockchainStateWrapper, ContractObjWrapper, TxTokenTransfer},
-    managed_address, managed_token_id, rust_biguint, DebugApi,
+    managed_address, managed_buffer, managed_token_id, rust_biguint, DebugApi,
 };
 
 pub static TOKEN_ID: &[u8] = b"MYTOK-123456";
+pub static CHAIN_ID: &[u8] = b"D";
 pub const FIRST_USER_EGLD_BALANCE: u64 = 500;
 pub const FIRST_USER_ESDT_BALANCE: u64 = 1_000;
 pub const SECOND_USER_ESDT_BALANCE: u64 = 2_000;
@@ -20,6 +21,24 @@ pub static EMPTY_SIG: &[u8; 64] = &[0u8; 64];
 
 pub static DEPOSIT_TOKENS_ENDPOINT_NAME: &[u8] = b"depositForUser";
 
+/// Only the cryptographic checks are skipped in debug builds,
+/// so the signing key IDs must still be valid
+pub fn key_signatures(key_ids: &[KeyId]) -> KeySignatures<DebugApi> {
+    let mut signatures = KeySignatures::new();
+    for &key_id in key_ids {
+        signatures.push(KeySignature {
+            key_id,
+            signature: ManagedBuffer::new_from_bytes(EMPTY_SIG),
+        });
+    }
+
+    signatures
+}
+
+pub fn address_key_signature() -> KeySignatures<DebugApi> {
+    key_signatures(&[ADDRESS_KEY_ID])
+}
+
 pub struct AbstractionSetup<AbstractionBuilder>
 where
     AbstractionBuilder: 'static + Copy + Fn() -> account_abstraction::ContractObj<DebugApi>,
@@ -64,13 +83,13 @@ where
 
         b_mock
             .execute_tx(&owner, &sc_wrapper, &rust_zero, |sc| {
-                sc.init();
+                sc.init(managed_buffer!(CHAIN_ID));
             })
             .assert_ok();
 
         b_mock
             .execute_tx(&owner, &mock_sc_wrapper, &rust_zero, |sc| {
-                sc.init();
+                sc.init(managed_buffer!(CHAIN_ID));
             })
             .assert_ok();
 
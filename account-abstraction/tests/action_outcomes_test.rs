@@ -0,0 +1,198 @@
+pub mod acc_abstraction_setup;
+
+use acc_abstraction_setup::*;
+use account_abstraction::common::{
+    action_outcomes::{ActionOutcomesModule, ActionRef, ActionStatus, MAX_USER_OUTCOMES},
+    common_types::{DEFAULT_NONCE_LANE, EGLD_TOKEN_ID},
+    custom_callbacks::{ChargedLimits, CustomCallbacksModule},
+};
+use multiversx_sc::{
+    imports::IgnoreValue,
+    types::{
+        Address, EsdtTokenPayment, ManagedAsyncCallError, ManagedAsyncCallResult, ManagedBuffer,
+        ManagedByteArray, ManagedVec,
+    },
+};
+use multiversx_sc_scenario::{
+    imports::TxTokenTransfer, managed_address, managed_biguint, managed_buffer, managed_token_id,
+    rust_biguint, DebugApi,
+};
+
+fn action_ref(action_index: u32) -> ActionRef<DebugApi> {
+    ActionRef {
+        tx_hash: ManagedByteArray::default(),
+        action_index,
+        nonce_lane: DEFAULT_NONCE_LANE,
+        opt_nonce: Some(action_index as u64),
+        opt_intent_id: None,
+    }
+}
+
+fn run_callback<AbstractionBuilder>(
+    setup: &mut AbstractionSetup<AbstractionBuilder>,
+    user: &Address,
+    action_index: u32,
+    call_result: fn() -> ManagedAsyncCallResult<DebugApi, IgnoreValue>,
+) where
+    AbstractionBuilder: 'static + Copy + Fn() -> account_abstraction::ContractObj<DebugApi>,
+{
+    let relayer = setup.second_user.clone();
+    setup
+        .b_mock
+        .execute_tx(&relayer, &setup.sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.user_action_cb(
+                managed_address!(user),
+                ManagedVec::from_single_item(EsdtTokenPayment::new(
+                    managed_token_id!(EGLD_TOKEN_ID),
+                    0,
+                    managed_biguint!(100),
+                )),
+                ChargedLimits {
+                    spent_at: 0,
+                    opt_session_id: None,
+                    opt_whitelist_action: None,
+                },
+                action_ref(action_index),
+                ManagedVec::new(),
+                managed_address!(&relayer),
+                None,
+                None,
+                call_result(),
+            );
+        })
+        .assert_ok();
+}
+
+#[test]
+fn action_outcomes_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    run_callback(&mut setup, &first_user_address, 0, || {
+        ManagedAsyncCallResult::Ok(IgnoreValue)
+    });
+    run_callback(&mut setup, &first_user_address, 1, || {
+        ManagedAsyncCallResult::Err(ManagedAsyncCallError {
+            err_code: 4,
+            err_msg: managed_buffer!(b"action failed"),
+        })
+    });
+
+    // failed action is refunded
+    let expected_first_user_tokens = [
+        TxTokenTransfer {
+            token_identifier: EGLD_TOKEN_ID.to_vec(),
+            nonce: 0,
+            value: rust_biguint!(FIRST_USER_EGLD_BALANCE + 100),
+        },
+        TxTokenTransfer {
+            token_identifier: TOKEN_ID.to_vec(),
+            nonce: 0,
+            value: rust_biguint!(FIRST_USER_ESDT_BALANCE),
+        },
+    ];
+    setup.check_user_tokens(&first_user_address, &expected_first_user_tokens);
+
+    setup
+        .b_mock
+        .execute_query(&setup.sc_wrapper, |sc| {
+            let outcomes: Vec<_> = sc
+                .get_action_outcomes(managed_address!(&first_user_address))
+                .into_iter()
+                .map(|multi_value| multi_value.into_tuple())
+                .collect();
+            assert_eq!(outcomes.len(), 2);
+
+            let (outcome_id, outcome) = &outcomes[0];
+            assert_eq!(*outcome_id, 1);
+            assert!(outcome.status == ActionStatus::Success);
+            assert_eq!(outcome.action_ref.action_index, 0);
+
+            let (outcome_id, outcome) = &outcomes[1];
+            assert_eq!(*outcome_id, 2);
+            assert!(outcome.status == ActionStatus::Failure);
+            assert_eq!(outcome.action_ref.action_index, 1);
+            assert_eq!(outcome.action_ref.opt_nonce, Some(1));
+            assert_eq!(outcome.error_code, 4);
+            assert_eq!(
+                outcome.error_message,
+                ManagedBuffer::new_from_bytes(b"action failed")
+            );
+        })
+        .assert_ok();
+
+    // only the latest outcomes are kept
+    for action_index in 2..(MAX_USER_OUTCOMES as u32 + 5) {
+        run_callback(&mut setup, &first_user_address, action_index, || {
+            ManagedAsyncCallResult::Ok(IgnoreValue)
+        });
+    }
+
+    setup
+        .b_mock
+        .execute_query(&setup.sc_wrapper, |sc| {
+            let outcomes: Vec<_> = sc
+                .get_action_outcomes(managed_address!(&first_user_address))
+                .into_iter()
+                .map(|multi_value| multi_value.into_tuple())
+                .collect();
+            assert_eq!(outcomes.len(), MAX_USER_OUTCOMES as usize);
+
+            let (first_outcome_id, first_outcome) = &outcomes[0];
+            assert_eq!(*first_outcome_id, 6);
+            assert_eq!(first_outcome.action_ref.action_index, 5);
+
+            let (last_outcome_id, _) = &outcomes[outcomes.len() - 1];
+            assert_eq!(*last_outcome_id, MAX_USER_OUTCOMES + 5);
+        })
+        .assert_ok();
+}
+
+#[test]
+fn min_returns_not_met_outcome_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let relayer = setup.second_user.clone();
+    setup
+        .b_mock
+        .execute_tx(&relayer, &setup.sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.user_action_cb(
+                managed_address!(&first_user_address),
+                ManagedVec::new(),
+                ChargedLimits {
+                    spent_at: 0,
+                    opt_session_id: None,
+                    opt_whitelist_action: None,
+                },
+                action_ref(0),
+                ManagedVec::from_single_item(EsdtTokenPayment::new(
+                    managed_token_id!(TOKEN_ID),
+                    0,
+                    managed_biguint!(50),
+                )),
+                managed_address!(&relayer),
+                None,
+                None,
+                ManagedAsyncCallResult::Ok(IgnoreValue),
+            );
+        })
+        .assert_ok();
+
+    setup
+        .b_mock
+        .execute_query(&setup.sc_wrapper, |sc| {
+            let outcomes: Vec<_> = sc
+                .get_action_outcomes(managed_address!(&first_user_address))
+                .into_iter()
+                .map(|multi_value| multi_value.into_tuple())
+                .collect();
+            assert_eq!(outcomes.len(), 1);
+
+            let (_, outcome) = &outcomes[0];
+            assert!(outcome.status == ActionStatus::MinReturnsNotMet);
+            assert_eq!(outcome.error_code, 0);
+            assert!(outcome.returned_payments.is_empty());
+        })
+        .assert_ok();
+}
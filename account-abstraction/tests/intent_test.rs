@@ -2,12 +2,22 @@ pub mod acc_abstraction_setup;
 
 use acc_abstraction_setup::*;
 use account_abstraction::{
-    common::common_types::{CallType, GeneralActionData, ScExecutionData, EGLD_TOKEN_ID},
-    user_actions::intents::IntentsModule,
+    common::{
+        common_types::{CallType, GeneralActionData, ScExecutionData, EGLD_TOKEN_ID},
+        users::UsersModule,
+    },
+    user_actions::{
+        intents::{
+            BalancePredicate, Comparator, Intent, IntentConditions, IntentPredicate, IntentType,
+            IntentsModule,
+        },
+        views::ViewsModule,
+    },
 };
-use multiversx_sc::types::{
-    EsdtTokenPayment, ManagedAddress, ManagedBuffer, ManagedByteArray, ManagedVec,
-    MultiValueEncoded,
+use multiversx_sc::{
+    codec::{NestedEncode, TopDecode, TopEncode},
+    imports::OptionalValue,
+    types::{EsdtTokenPayment, ManagedAddress, ManagedBuffer, ManagedVec, MultiValueEncoded},
 };
 use multiversx_sc_scenario::{
     imports::TxTokenTransfer, managed_address, managed_biguint, managed_buffer, managed_token_id,
@@ -51,9 +61,12 @@ fn intent_test() {
                                 args,
                                 gas_limit: 10_000,
                             }),
+                            min_returns: ManagedVec::new(),
+                            opt_relayer_fee: None,
                         },
+                        IntentConditions::default(),
                         0u64,
-                        ManagedByteArray::new_from_bytes(EMPTY_SIG),
+                        address_key_signature(),
                     )
                         .into(),
                 );
@@ -113,3 +126,614 @@ fn intent_test() {
     }];
     setup.check_user_tokens_mock(&second_user_address, &expected_second_user_tokens);
 }
+
+#[test]
+fn intent_double_execution_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+    let mock_address = setup.mock_sc_wrapper.address_ref().clone();
+    setup
+        .b_mock
+        .execute_tx(
+            &setup.first_user,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut actions = MultiValueEncoded::new();
+                let mut args = ManagedVec::new();
+                args.push(ManagedBuffer::new_from_bytes(
+                    ManagedAddress::<DebugApi>::from_address(&second_user_address)
+                        .to_byte_array()
+                        .as_slice(),
+                ));
+
+                actions.push(
+                    (
+                        GeneralActionData {
+                            call_type: CallType::Async,
+                            dest_address: managed_address!(&mock_address),
+                            payments: ManagedVec::from_single_item(EsdtTokenPayment::new(
+                                managed_token_id!(EGLD_TOKEN_ID),
+                                0,
+                                managed_biguint!(100),
+                            )),
+                            opt_execution: Some(ScExecutionData {
+                                endpoint_name: managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+                                args,
+                                gas_limit: 10_000,
+                            }),
+                            min_returns: ManagedVec::new(),
+                            opt_relayer_fee: None,
+                        },
+                        IntentConditions::default(),
+                        0u64,
+                        address_key_signature(),
+                    )
+                        .into(),
+                );
+
+                sc.save_intents(managed_address!(&first_user_address), actions);
+            },
+        )
+        .assert_ok();
+
+    // intent is locked while the promise is pending
+    setup
+        .b_mock
+        .execute_tx(
+            &setup.first_user,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.execute_intent(managed_address!(&first_user_address), 1);
+
+                let intent = sc.get_intent_info(managed_address!(&first_user_address), 1);
+                assert!(matches!(intent.intent_type, IntentType::InProgress));
+
+                sc.execute_intent(managed_address!(&first_user_address), 1);
+            },
+        )
+        .assert_user_error("Intent execution already in progress");
+
+    setup
+        .b_mock
+        .execute_tx(
+            &setup.first_user,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.execute_intent(managed_address!(&first_user_address), 1);
+            },
+        )
+        .assert_ok();
+
+    // intent is removed after successful execution
+    setup
+        .b_mock
+        .execute_tx(
+            &setup.first_user,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.execute_intent(managed_address!(&first_user_address), 1);
+            },
+        )
+        .assert_user_error("Intent doesn't exist");
+
+    // funds were only sent once
+    let expected_second_user_tokens = [TxTokenTransfer {
+        token_identifier: EGLD_TOKEN_ID.to_vec(),
+        nonce: 0,
+        value: rust_biguint!(100),
+    }];
+    setup.check_user_tokens_mock(&second_user_address, &expected_second_user_tokens);
+}
+
+#[test]
+fn cancel_intent_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+    let mock_address = setup.mock_sc_wrapper.address_ref().clone();
+    setup
+        .b_mock
+        .execute_tx(
+            &setup.first_user,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut actions = MultiValueEncoded::new();
+                let mut args = ManagedVec::new();
+                args.push(ManagedBuffer::new_from_bytes(
+                    ManagedAddress::<DebugApi>::from_address(&second_user_address)
+                        .to_byte_array()
+                        .as_slice(),
+                ));
+
+                actions.push(
+                    (
+                        GeneralActionData {
+                            call_type: CallType::Async,
+                            dest_address: managed_address!(&mock_address),
+                            payments: ManagedVec::from_single_item(EsdtTokenPayment::new(
+                                managed_token_id!(EGLD_TOKEN_ID),
+                                0,
+                                managed_biguint!(100),
+                            )),
+                            opt_execution: Some(ScExecutionData {
+                                endpoint_name: managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+                                args,
+                                gas_limit: 10_000,
+                            }),
+                            min_returns: ManagedVec::new(),
+                            opt_relayer_fee: None,
+                        },
+                        IntentConditions::default(),
+                        0u64,
+                        address_key_signature(),
+                    )
+                        .into(),
+                );
+
+                sc.save_intents(managed_address!(&first_user_address), actions);
+            },
+        )
+        .assert_ok();
+
+    // try cancel from another address without signature
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.cancel_intent(
+                    managed_address!(&first_user_address),
+                    1,
+                    OptionalValue::None,
+                );
+            },
+        )
+        .assert_user_error("Signature required");
+
+    // cancel directly as user
+    setup
+        .b_mock
+        .execute_tx(
+            &first_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.cancel_intent(
+                    managed_address!(&first_user_address),
+                    1,
+                    OptionalValue::None,
+                );
+
+                assert!(sc
+                    .get_all_user_intent_ids(managed_address!(&first_user_address))
+                    .is_empty());
+            },
+        )
+        .assert_ok();
+
+    // reserved funds were refunded
+    let expected_first_user_tokens = [
+        TxTokenTransfer {
+            token_identifier: EGLD_TOKEN_ID.to_vec(),
+            nonce: 0,
+            value: rust_biguint!(FIRST_USER_EGLD_BALANCE),
+        },
+        TxTokenTransfer {
+            token_identifier: TOKEN_ID.to_vec(),
+            nonce: 0,
+            value: rust_biguint!(FIRST_USER_ESDT_BALANCE),
+        },
+    ];
+    setup.check_user_tokens(&first_user_address, &expected_first_user_tokens);
+
+    // intent can no longer be executed
+    setup
+        .b_mock
+        .execute_tx(
+            &first_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.execute_intent(managed_address!(&first_user_address), 1);
+            },
+        )
+        .assert_user_error("Intent doesn't exist");
+}
+
+#[test]
+fn intent_expiry_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+    let mock_address = setup.mock_sc_wrapper.address_ref().clone();
+    setup.b_mock.set_block_timestamp(10);
+
+    setup
+        .b_mock
+        .execute_tx(
+            &setup.first_user,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut actions = MultiValueEncoded::new();
+                let mut args = ManagedVec::new();
+                args.push(ManagedBuffer::new_from_bytes(
+                    ManagedAddress::<DebugApi>::from_address(&second_user_address)
+                        .to_byte_array()
+                        .as_slice(),
+                ));
+
+                actions.push(
+                    (
+                        GeneralActionData {
+                            call_type: CallType::Async,
+                            dest_address: managed_address!(&mock_address),
+                            payments: ManagedVec::from_single_item(EsdtTokenPayment::new(
+                                managed_token_id!(EGLD_TOKEN_ID),
+                                0,
+                                managed_biguint!(100),
+                            )),
+                            opt_execution: Some(ScExecutionData {
+                                endpoint_name: managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+                                args,
+                                gas_limit: 10_000,
+                            }),
+                            min_returns: ManagedVec::new(),
+                            opt_relayer_fee: None,
+                        },
+                        IntentConditions {
+                            opt_valid_after: Some(20),
+                            opt_valid_until: Some(30),
+                            opt_predicate: None,
+                            opt_balance_predicate: None,
+                        },
+                        0u64,
+                        address_key_signature(),
+                    )
+                        .into(),
+                );
+
+                sc.save_intents(managed_address!(&first_user_address), actions);
+            },
+        )
+        .assert_ok();
+
+    // try execute before validity window
+    setup
+        .b_mock
+        .execute_tx(
+            &setup.first_user,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.execute_intent(managed_address!(&first_user_address), 1);
+            },
+        )
+        .assert_user_error("Intent not yet valid");
+
+    // try cleanup before expiry
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut intent_ids = MultiValueEncoded::new();
+                intent_ids.push(1);
+
+                sc.cleanup_expired_intents(managed_address!(&first_user_address), intent_ids);
+            },
+        )
+        .assert_user_error("Intent not expired");
+
+    setup.b_mock.set_block_timestamp(31);
+
+    // try execute after validity window
+    setup
+        .b_mock
+        .execute_tx(
+            &setup.first_user,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.execute_intent(managed_address!(&first_user_address), 1);
+            },
+        )
+        .assert_user_error("Intent expired");
+
+    // anyone can cleanup
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut intent_ids = MultiValueEncoded::new();
+                intent_ids.push(1);
+
+                sc.cleanup_expired_intents(managed_address!(&first_user_address), intent_ids);
+            },
+        )
+        .assert_ok();
+
+    // reserved funds were refunded
+    let expected_first_user_tokens = [
+        TxTokenTransfer {
+            token_identifier: EGLD_TOKEN_ID.to_vec(),
+            nonce: 0,
+            value: rust_biguint!(FIRST_USER_EGLD_BALANCE),
+        },
+        TxTokenTransfer {
+            token_identifier: TOKEN_ID.to_vec(),
+            nonce: 0,
+            value: rust_biguint!(FIRST_USER_ESDT_BALANCE),
+        },
+    ];
+    setup.check_user_tokens(&first_user_address, &expected_first_user_tokens);
+}
+
+#[test]
+fn intent_predicate_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+    let mock_address = setup.mock_sc_wrapper.address_ref().clone();
+    setup
+        .b_mock
+        .execute_tx(
+            &setup.first_user,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut actions = MultiValueEncoded::new();
+                let mut args = ManagedVec::new();
+                args.push(ManagedBuffer::new_from_bytes(
+                    ManagedAddress::<DebugApi>::from_address(&second_user_address)
+                        .to_byte_array()
+                        .as_slice(),
+                ));
+
+                // second user's nonce in the mock SC is 0
+                let predicates = [
+                    (Comparator::GreaterOrEqual, 1u64),
+                    (Comparator::Equal, 0u64),
+                ];
+                for (user_nonce, (comparator, expected_value)) in predicates.iter().enumerate() {
+                    actions.push(
+                        (
+                            GeneralActionData {
+                                call_type: CallType::Async,
+                                dest_address: managed_address!(&mock_address),
+                                payments: ManagedVec::from_single_item(EsdtTokenPayment::new(
+                                    managed_token_id!(EGLD_TOKEN_ID),
+                                    0,
+                                    managed_biguint!(100),
+                                )),
+                                opt_execution: Some(ScExecutionData {
+                                    endpoint_name: managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+                                    args: args.clone(),
+                                    gas_limit: 10_000,
+                                }),
+                                min_returns: ManagedVec::new(),
+                                opt_relayer_fee: None,
+                            },
+                            IntentConditions {
+                                opt_valid_after: None,
+                                opt_valid_until: None,
+                                opt_predicate: Some(IntentPredicate {
+                                    sc_address: managed_address!(&mock_address),
+                                    view_data: ScExecutionData {
+                                        endpoint_name: managed_buffer!(b"getUserNonce"),
+                                        args: args.clone(),
+                                        gas_limit: 1_000_000,
+                                    },
+                                    comparator: *comparator,
+                                    expected_value: managed_biguint!(*expected_value),
+                                }),
+                                opt_balance_predicate: None,
+                            },
+                            user_nonce as u64,
+                            address_key_signature(),
+                        )
+                            .into(),
+                    );
+                }
+
+                sc.save_intents(managed_address!(&first_user_address), actions);
+            },
+        )
+        .assert_ok();
+
+    setup
+        .b_mock
+        .execute_tx(
+            &setup.first_user,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.execute_intent(managed_address!(&first_user_address), 1);
+            },
+        )
+        .assert_user_error("Intent condition not met");
+
+    setup
+        .b_mock
+        .execute_tx(
+            &setup.first_user,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.execute_intent(managed_address!(&first_user_address), 2);
+            },
+        )
+        .assert_ok();
+
+    // check second user tokens in mock
+    let expected_second_user_tokens = [TxTokenTransfer {
+        token_identifier: EGLD_TOKEN_ID.to_vec(),
+        nonce: 0,
+        value: rust_biguint!(100),
+    }];
+    setup.check_user_tokens_mock(&second_user_address, &expected_second_user_tokens);
+}
+
+#[test]
+fn intent_balance_predicate_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+    let mock_address = setup.mock_sc_wrapper.address_ref().clone();
+    setup
+        .b_mock
+        .execute_tx(
+            &setup.first_user,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut actions = MultiValueEncoded::new();
+                let mut args = ManagedVec::new();
+                args.push(ManagedBuffer::new_from_bytes(
+                    ManagedAddress::<DebugApi>::from_address(&second_user_address)
+                        .to_byte_array()
+                        .as_slice(),
+                ));
+
+                actions.push(
+                    (
+                        GeneralActionData {
+                            call_type: CallType::Async,
+                            dest_address: managed_address!(&mock_address),
+                            payments: ManagedVec::from_single_item(EsdtTokenPayment::new(
+                                managed_token_id!(EGLD_TOKEN_ID),
+                                0,
+                                managed_biguint!(100),
+                            )),
+                            opt_execution: Some(ScExecutionData {
+                                endpoint_name: managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+                                args,
+                                gas_limit: 10_000,
+                            }),
+                            min_returns: ManagedVec::new(),
+                            opt_relayer_fee: None,
+                        },
+                        IntentConditions {
+                            opt_valid_after: None,
+                            opt_valid_until: None,
+                            opt_predicate: None,
+                            opt_balance_predicate: Some(BalancePredicate {
+                                token_id: managed_token_id!(TOKEN_ID),
+                                token_nonce: 0,
+                                comparator: Comparator::GreaterOrEqual,
+                                expected_value: managed_biguint!(FIRST_USER_ESDT_BALANCE + 500),
+                            }),
+                        },
+                        0u64,
+                        address_key_signature(),
+                    )
+                        .into(),
+                );
+
+                sc.save_intents(managed_address!(&first_user_address), actions);
+            },
+        )
+        .assert_ok();
+
+    // try execute before the user's balance is high enough
+    setup
+        .b_mock
+        .execute_tx(
+            &setup.first_user,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.execute_intent(managed_address!(&first_user_address), 1);
+            },
+        )
+        .assert_user_error("Intent condition not met");
+
+    setup
+        .b_mock
+        .set_esdt_balance(&first_user_address, TOKEN_ID, &rust_biguint!(500));
+    setup
+        .b_mock
+        .execute_esdt_transfer(
+            &first_user_address,
+            &setup.sc_wrapper,
+            TOKEN_ID,
+            0,
+            &rust_biguint!(500),
+            |sc| {
+                sc.deposit_for_user(managed_address!(&first_user_address));
+            },
+        )
+        .assert_ok();
+
+    setup
+        .b_mock
+        .execute_tx(
+            &setup.first_user,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.execute_intent(managed_address!(&first_user_address), 1);
+            },
+        )
+        .assert_ok();
+
+    // check second user tokens in mock
+    let expected_second_user_tokens = [TxTokenTransfer {
+        token_identifier: EGLD_TOKEN_ID.to_vec(),
+        nonce: 0,
+        value: rust_biguint!(100),
+    }];
+    setup.check_user_tokens_mock(&second_user_address, &expected_second_user_tokens);
+}
+
+#[test]
+fn legacy_intent_layout_test() {
+    let _ = DebugApi::dummy();
+
+    // intents stored before conditions and the trailing action fields existed
+    let payments = ManagedVec::<DebugApi, _>::from_single_item(EsdtTokenPayment::new(
+        managed_token_id!(EGLD_TOKEN_ID),
+        0,
+        managed_biguint!(100),
+    ));
+    let mut legacy_action = ManagedBuffer::<DebugApi>::new();
+    let _ = CallType::Async.dep_encode(&mut legacy_action);
+    let _ = ManagedAddress::<DebugApi>::zero().dep_encode(&mut legacy_action);
+    let _ = payments.dep_encode(&mut legacy_action);
+    let _ = Option::<ScExecutionData<DebugApi>>::None.dep_encode(&mut legacy_action);
+
+    let mut legacy_intent = ManagedBuffer::<DebugApi>::new();
+    let _ = IntentType::AwaitingExecution.dep_encode(&mut legacy_intent);
+    legacy_intent.append(&legacy_action);
+
+    let intent = Intent::<DebugApi>::top_decode(legacy_intent).unwrap();
+    assert!(matches!(intent.intent_type, IntentType::AwaitingExecution));
+    assert!(intent.intent_data.opt_relayer_fee.is_none());
+    assert!(intent.intent_data.min_returns.is_empty());
+    assert!(intent.conditions.opt_valid_until.is_none());
+    assert!(intent.conditions.opt_predicate.is_none());
+    assert!(intent.opt_saved_at.is_none());
+
+    // without the trailing fields, the action is signed in the original layout
+    let mut encoded_action = ManagedBuffer::<DebugApi>::new();
+    let _ = intent.intent_data.top_encode(&mut encoded_action);
+    assert_eq!(encoded_action, legacy_action);
+}
@@ -0,0 +1,484 @@
+pub mod acc_abstraction_setup;
+
+use acc_abstraction_setup::*;
+use account_abstraction::{
+    common::{
+        common_types::{CallType, GeneralActionData, DEFAULT_NONCE_LANE, EGLD_TOKEN_ID},
+        keys::KeysModule,
+        signature::{KeyId, KeyType, SignatureModule, SigningKey},
+        users::UsersModule,
+    },
+    user_actions::execution::ExecutionModule,
+};
+use multiversx_sc::{
+    imports::OptionalValue,
+    types::{EsdtTokenPayment, ManagedBuffer, ManagedVec, MultiValueEncoded},
+};
+use multiversx_sc_scenario::{
+    imports::TxResult, managed_address, managed_biguint, managed_token_id, rust_biguint, DebugApi,
+};
+
+pub static NEW_KEY: &[u8; 32] = &[1u8; 32];
+pub static ROTATED_KEY: &[u8; 33] = &[2u8; 33];
+
+/// First user sends 100 EGLD to the second user, signed by the given keys
+fn transfer_signed_by<AbstractionBuilder>(
+    setup: &mut AbstractionSetup<AbstractionBuilder>,
+    key_ids: &[KeyId],
+) -> TxResult
+where
+    AbstractionBuilder: 'static + Copy + Fn() -> account_abstraction::ContractObj<DebugApi>,
+{
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+    setup.b_mock.execute_tx(
+        &second_user_address,
+        &setup.sc_wrapper,
+        &rust_biguint!(0),
+        |sc| {
+            let mut actions = MultiValueEncoded::new();
+            actions.push(
+                (
+                    GeneralActionData {
+                        call_type: CallType::Transfer,
+                        dest_address: managed_address!(&second_user_address),
+                        payments: ManagedVec::from_single_item(EsdtTokenPayment::new(
+                            managed_token_id!(EGLD_TOKEN_ID),
+                            0,
+                            managed_biguint!(100),
+                        )),
+                        opt_execution: None,
+                        min_returns: ManagedVec::new(),
+                        opt_relayer_fee: None,
+                    },
+                    DEFAULT_NONCE_LANE,
+                    2u64,
+                    key_signatures(key_ids),
+                )
+                    .into(),
+            );
+
+            sc.multi_action_for_user(managed_address!(&first_user_address), actions);
+        },
+    )
+}
+
+#[test]
+fn user_keys_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+
+    // address key is registered on user registration
+    setup
+        .b_mock
+        .execute_query(&setup.sc_wrapper, |sc| {
+            let keys: Vec<_> = sc
+                .get_user_keys(managed_address!(&first_user_address))
+                .into_iter()
+                .collect();
+            assert_eq!(keys.len(), 1);
+
+            let (key_id, signing_key) = keys.into_iter().next().unwrap().into_tuple();
+            assert_eq!(key_id, 1);
+            assert!(signing_key.key_type == KeyType::Ed25519);
+            assert_eq!(
+                signing_key.public_key,
+                ManagedBuffer::new_from_bytes(first_user_address.as_bytes())
+            );
+        })
+        .assert_ok();
+
+    // try remove last key
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.remove_user_key(
+                    managed_address!(&first_user_address),
+                    1,
+                    0,
+                    address_key_signature(),
+                );
+            },
+        )
+        .assert_user_error("Cannot remove last key");
+
+    // try add key with invalid length
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let _ = sc.add_user_key(
+                    managed_address!(&first_user_address),
+                    SigningKey {
+                        key_type: KeyType::Secp256k1,
+                        public_key: ManagedBuffer::new_from_bytes(NEW_KEY),
+                    },
+                    0,
+                    address_key_signature(),
+                );
+            },
+        )
+        .assert_user_error("Invalid public key length");
+
+    // try add a passkey, not verifiable yet
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let _ = sc.add_user_key(
+                    managed_address!(&first_user_address),
+                    SigningKey {
+                        key_type: KeyType::Secp256r1,
+                        public_key: ManagedBuffer::new_from_bytes(ROTATED_KEY),
+                    },
+                    0,
+                    address_key_signature(),
+                );
+            },
+        )
+        .assert_user_error("Key type not supported");
+
+    // add new key, relayed by another user
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let key_id = sc.add_user_key(
+                    managed_address!(&first_user_address),
+                    SigningKey {
+                        key_type: KeyType::Ed25519,
+                        public_key: ManagedBuffer::new_from_bytes(NEW_KEY),
+                    },
+                    0,
+                    address_key_signature(),
+                );
+                assert_eq!(key_id, 2);
+            },
+        )
+        .assert_ok();
+
+    // try add same key again
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let _ = sc.add_user_key(
+                    managed_address!(&first_user_address),
+                    SigningKey {
+                        key_type: KeyType::Ed25519,
+                        public_key: ManagedBuffer::new_from_bytes(NEW_KEY),
+                    },
+                    1,
+                    address_key_signature(),
+                );
+            },
+        )
+        .assert_user_error("Key already registered");
+
+    // remove the compromised address key
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.remove_user_key(
+                    managed_address!(&first_user_address),
+                    1,
+                    1,
+                    address_key_signature(),
+                );
+            },
+        )
+        .assert_ok();
+
+    // rotate the remaining key
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.rotate_user_key(
+                    managed_address!(&first_user_address),
+                    2,
+                    SigningKey {
+                        key_type: KeyType::Secp256k1,
+                        public_key: ManagedBuffer::new_from_bytes(ROTATED_KEY),
+                    },
+                    2,
+                    key_signatures(&[2]),
+                );
+            },
+        )
+        .assert_ok();
+
+    setup
+        .b_mock
+        .execute_query(&setup.sc_wrapper, |sc| {
+            let keys: Vec<_> = sc
+                .get_user_keys(managed_address!(&first_user_address))
+                .into_iter()
+                .collect();
+            assert_eq!(keys.len(), 1);
+
+            let (key_id, signing_key) = keys.into_iter().next().unwrap().into_tuple();
+            assert_eq!(key_id, 2);
+            assert!(signing_key.key_type == KeyType::Secp256k1);
+            assert_eq!(
+                signing_key.public_key,
+                ManagedBuffer::new_from_bytes(ROTATED_KEY)
+            );
+        })
+        .assert_ok();
+
+    // try replay an old nonce
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.remove_user_key(
+                    managed_address!(&first_user_address),
+                    2,
+                    2,
+                    key_signatures(&[2]),
+                );
+            },
+        )
+        .assert_user_error("Invalid user nonce");
+
+    // the removed address key can't act through direct calls either
+    setup
+        .b_mock
+        .execute_tx(
+            &first_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.withdraw(
+                    ManagedVec::from_single_item(EsdtTokenPayment::new(
+                        managed_token_id!(EGLD_TOKEN_ID),
+                        0,
+                        managed_biguint!(100),
+                    )),
+                    OptionalValue::None,
+                );
+            },
+        )
+        .assert_user_error("Address key not authorized");
+}
+
+#[test]
+fn multisig_account_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+
+    // add a second signer
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let _ = sc.add_user_key(
+                    managed_address!(&first_user_address),
+                    SigningKey {
+                        key_type: KeyType::Ed25519,
+                        public_key: ManagedBuffer::new_from_bytes(NEW_KEY),
+                    },
+                    0,
+                    address_key_signature(),
+                );
+            },
+        )
+        .assert_ok();
+
+    // try set threshold above the number of keys
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.set_signature_threshold(
+                    managed_address!(&first_user_address),
+                    3,
+                    1,
+                    address_key_signature(),
+                );
+            },
+        )
+        .assert_user_error("Invalid threshold");
+
+    // 2-of-2
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.set_signature_threshold(
+                    managed_address!(&first_user_address),
+                    2,
+                    1,
+                    address_key_signature(),
+                );
+            },
+        )
+        .assert_ok();
+
+    setup
+        .b_mock
+        .execute_query(&setup.sc_wrapper, |sc| {
+            let threshold = sc.get_signature_threshold(managed_address!(&first_user_address));
+            assert_eq!(threshold, 2);
+
+            let keys: Vec<_> = sc
+                .get_user_keys(managed_address!(&first_user_address))
+                .into_iter()
+                .collect();
+            assert_eq!(keys.len(), 2);
+        })
+        .assert_ok();
+
+    // try remove a signer, which would make the threshold unreachable
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.remove_user_key(
+                    managed_address!(&first_user_address),
+                    1,
+                    2,
+                    key_signatures(&[1, 2]),
+                );
+            },
+        )
+        .assert_user_error("Threshold higher than key count");
+
+    // every signature counts once, and only for the user's registered keys
+    transfer_signed_by(&mut setup, &[1]).assert_user_error("Not enough signatures");
+    transfer_signed_by(&mut setup, &[1, 1]).assert_user_error("Duplicate signing key");
+    transfer_signed_by(&mut setup, &[1, 3]).assert_user_error("Unknown signing key");
+
+    // execute action signed by both keys
+    transfer_signed_by(&mut setup, &[1, 2]).assert_ok();
+
+    setup
+        .b_mock
+        .check_egld_balance(&second_user_address, &rust_biguint!(100));
+}
+
+#[test]
+fn legacy_user_add_key_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+
+    // users registered before key sets existed have no stored keys
+    setup
+        .b_mock
+        .execute_tx(&setup.owner, &setup.sc_wrapper, &rust_biguint!(0), |sc| {
+            let user_id = sc.user_ids().get_id(&managed_address!(&first_user_address));
+            sc.user_key(user_id, 1).clear();
+            sc.user_key_ids(user_id).clear();
+            sc.user_last_key_id(user_id).clear();
+        })
+        .assert_ok();
+
+    // the address key is stored first, so the new key doesn't replace it
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let key_id = sc.add_user_key(
+                    managed_address!(&first_user_address),
+                    SigningKey {
+                        key_type: KeyType::Ed25519,
+                        public_key: ManagedBuffer::new_from_bytes(NEW_KEY),
+                    },
+                    0,
+                    address_key_signature(),
+                );
+                assert_eq!(key_id, 2);
+            },
+        )
+        .assert_ok();
+
+    setup
+        .b_mock
+        .execute_query(&setup.sc_wrapper, |sc| {
+            let keys: Vec<_> = sc
+                .get_user_keys(managed_address!(&first_user_address))
+                .into_iter()
+                .collect();
+            assert_eq!(keys.len(), 2);
+
+            let (key_id, signing_key) = keys.into_iter().next().unwrap().into_tuple();
+            assert_eq!(key_id, 1);
+            assert_eq!(
+                signing_key.public_key,
+                ManagedBuffer::new_from_bytes(first_user_address.as_bytes())
+            );
+        })
+        .assert_ok();
+
+    // the address key still works
+    setup
+        .b_mock
+        .execute_tx(
+            &first_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.withdraw(
+                    ManagedVec::from_single_item(EsdtTokenPayment::new(
+                        managed_token_id!(EGLD_TOKEN_ID),
+                        0,
+                        managed_biguint!(100),
+                    )),
+                    OptionalValue::None,
+                );
+            },
+        )
+        .assert_ok();
+
+    setup
+        .b_mock
+        .check_egld_balance(&first_user_address, &rust_biguint!(100));
+}
@@ -3,15 +3,20 @@ pub mod acc_abstraction_setup;
 use acc_abstraction_setup::*;
 use account_abstraction::{
     common::{
-        common_types::{CallType, GeneralActionData, ScExecutionData, EGLD_TOKEN_ID},
+        common_types::{
+            CallType, GeneralActionData, PaymentsVec, RelayerFee, ScExecutionData,
+            DEFAULT_NONCE_LANE, EGLD_TOKEN_ID,
+        },
+        nonces::NoncesModule,
         signature::Signature,
         users::UsersModule,
     },
     user_actions::execution::ExecutionModule,
 };
-use multiversx_sc::types::{
-    EsdtTokenPayment, ManagedAddress, ManagedBuffer, ManagedByteArray, ManagedVec,
-    MultiValueEncoded,
+use multiversx_sc::{
+    codec::TopEncode,
+    imports::OptionalValue,
+    types::{EsdtTokenPayment, ManagedAddress, ManagedBuffer, ManagedVec, MultiValueEncoded},
 };
 use multiversx_sc_scenario::{
     imports::TxTokenTransfer, managed_address, managed_biguint, managed_buffer, managed_token_id,
@@ -65,9 +70,12 @@ fn execute_action_transfer_test() {
                                 managed_biguint!(100),
                             )),
                             opt_execution: None,
+                            min_returns: ManagedVec::new(),
+                            opt_relayer_fee: None,
                         },
+                        DEFAULT_NONCE_LANE,
                         0u64,
-                        ManagedByteArray::new_from_bytes(EMPTY_SIG),
+                        address_key_signature(),
                     )
                         .into(),
                 );
@@ -134,9 +142,12 @@ fn execute_action_sc_call_async_test() {
                                 args,
                                 gas_limit: 10_000,
                             }),
+                            min_returns: ManagedVec::new(),
+                            opt_relayer_fee: None,
                         },
+                        DEFAULT_NONCE_LANE,
                         0u64,
-                        ManagedByteArray::new_from_bytes(EMPTY_SIG),
+                        address_key_signature(),
                     )
                         .into(),
                 );
@@ -207,9 +218,12 @@ fn execute_action_sc_call_sync_test() {
                                 args,
                                 gas_limit: 10_000,
                             }),
+                            min_returns: ManagedVec::new(),
+                            opt_relayer_fee: None,
                         },
+                        DEFAULT_NONCE_LANE,
                         0u64,
-                        ManagedByteArray::new_from_bytes(EMPTY_SIG),
+                        address_key_signature(),
                     )
                         .into(),
                 );
@@ -268,9 +282,12 @@ fn execute_action_transfer_esdt_test() {
                                 managed_biguint!(100),
                             )),
                             opt_execution: None,
+                            min_returns: ManagedVec::new(),
+                            opt_relayer_fee: None,
                         },
+                        DEFAULT_NONCE_LANE,
                         0u64,
-                        ManagedByteArray::new_from_bytes(EMPTY_SIG),
+                        address_key_signature(),
                     )
                         .into(),
                 );
@@ -337,9 +354,12 @@ fn execute_action_sc_call_async_esdt_test() {
                                 args,
                                 gas_limit: 10_000,
                             }),
+                            min_returns: ManagedVec::new(),
+                            opt_relayer_fee: None,
                         },
+                        DEFAULT_NONCE_LANE,
                         0u64,
-                        ManagedByteArray::new_from_bytes(EMPTY_SIG),
+                        address_key_signature(),
                     )
                         .into(),
                 );
@@ -410,9 +430,12 @@ fn execute_action_sc_call_sync_esdt_test() {
                                 args,
                                 gas_limit: 10_000,
                             }),
+                            min_returns: ManagedVec::new(),
+                            opt_relayer_fee: None,
                         },
+                        DEFAULT_NONCE_LANE,
                         0u64,
-                        ManagedByteArray::new_from_bytes(EMPTY_SIG),
+                        address_key_signature(),
                     )
                         .into(),
                 );
@@ -445,3 +468,777 @@ fn execute_action_sc_call_sync_esdt_test() {
     }];
     setup.check_user_tokens_mock(&second_user_address, &expected_second_user_tokens);
 }
+
+#[test]
+fn withdraw_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+
+    // try withdraw more than deposited
+    setup
+        .b_mock
+        .execute_tx(
+            &first_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.withdraw(
+                    ManagedVec::from_single_item(EsdtTokenPayment::new(
+                        managed_token_id!(TOKEN_ID),
+                        0,
+                        managed_biguint!(FIRST_USER_ESDT_BALANCE + 1),
+                    )),
+                    OptionalValue::None,
+                );
+            },
+        )
+        .assert_user_error("Not enough tokens");
+
+    // withdraw EGLD and ESDT to self
+    setup
+        .b_mock
+        .execute_tx(
+            &first_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut payments = ManagedVec::new();
+                payments.push(EsdtTokenPayment::new(
+                    managed_token_id!(EGLD_TOKEN_ID),
+                    0,
+                    managed_biguint!(100),
+                ));
+                payments.push(EsdtTokenPayment::new(
+                    managed_token_id!(TOKEN_ID),
+                    0,
+                    managed_biguint!(200),
+                ));
+
+                sc.withdraw(payments, OptionalValue::None);
+            },
+        )
+        .assert_ok();
+
+    let expected_first_user_tokens = [
+        TxTokenTransfer {
+            token_identifier: EGLD_TOKEN_ID.to_vec(),
+            nonce: 0,
+            value: rust_biguint!(FIRST_USER_EGLD_BALANCE - 100),
+        },
+        TxTokenTransfer {
+            token_identifier: TOKEN_ID.to_vec(),
+            nonce: 0,
+            value: rust_biguint!(FIRST_USER_ESDT_BALANCE - 200),
+        },
+    ];
+    setup.check_user_tokens(&first_user_address, &expected_first_user_tokens);
+
+    setup
+        .b_mock
+        .check_egld_balance(&first_user_address, &rust_biguint!(100));
+    setup
+        .b_mock
+        .check_esdt_balance(&first_user_address, TOKEN_ID, &rust_biguint!(200));
+
+    // relayed withdraw to second user
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.withdraw_for_user(
+                    managed_address!(&first_user_address),
+                    managed_address!(&second_user_address),
+                    ManagedVec::from_single_item(EsdtTokenPayment::new(
+                        managed_token_id!(EGLD_TOKEN_ID),
+                        0,
+                        managed_biguint!(50),
+                    )),
+                    0u64,
+                    address_key_signature(),
+                );
+            },
+        )
+        .assert_ok();
+
+    setup
+        .b_mock
+        .check_egld_balance(&second_user_address, &rust_biguint!(50));
+
+    // try reuse the same nonce
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.withdraw_for_user(
+                    managed_address!(&first_user_address),
+                    managed_address!(&second_user_address),
+                    ManagedVec::from_single_item(EsdtTokenPayment::new(
+                        managed_token_id!(EGLD_TOKEN_ID),
+                        0,
+                        managed_biguint!(50),
+                    )),
+                    0u64,
+                    address_key_signature(),
+                );
+            },
+        )
+        .assert_user_error("Invalid user nonce");
+}
+
+#[test]
+fn execute_action_sc_call_sync_min_returns_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+    let mock_address = setup.mock_sc_wrapper.address_ref().clone();
+
+    // deposit sends nothing back
+    setup
+        .b_mock
+        .execute_tx(
+            &setup.first_user,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut actions = MultiValueEncoded::new();
+                let mut args = ManagedVec::new();
+                args.push(ManagedBuffer::new_from_bytes(
+                    ManagedAddress::<DebugApi>::from_address(&second_user_address)
+                        .to_byte_array()
+                        .as_slice(),
+                ));
+
+                actions.push(
+                    (
+                        GeneralActionData {
+                            call_type: CallType::Sync,
+                            dest_address: managed_address!(&mock_address),
+                            payments: ManagedVec::from_single_item(EsdtTokenPayment::new(
+                                managed_token_id!(TOKEN_ID),
+                                0,
+                                managed_biguint!(100),
+                            )),
+                            opt_execution: Some(ScExecutionData {
+                                endpoint_name: managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+                                args,
+                                gas_limit: 10_000,
+                            }),
+                            min_returns: ManagedVec::from_single_item(EsdtTokenPayment::new(
+                                managed_token_id!(EGLD_TOKEN_ID),
+                                0,
+                                managed_biguint!(1),
+                            )),
+                            opt_relayer_fee: None,
+                        },
+                        DEFAULT_NONCE_LANE,
+                        0u64,
+                        address_key_signature(),
+                    )
+                        .into(),
+                );
+
+                sc.multi_action_for_user(managed_address!(&first_user_address), actions);
+            },
+        )
+        .assert_user_error("Min returns not met");
+
+    // nothing was spent
+    let expected_first_user_tokens = [
+        TxTokenTransfer {
+            token_identifier: EGLD_TOKEN_ID.to_vec(),
+            nonce: 0,
+            value: rust_biguint!(FIRST_USER_EGLD_BALANCE),
+        },
+        TxTokenTransfer {
+            token_identifier: TOKEN_ID.to_vec(),
+            nonce: 0,
+            value: rust_biguint!(FIRST_USER_ESDT_BALANCE),
+        },
+    ];
+    setup.check_user_tokens(&first_user_address, &expected_first_user_tokens);
+}
+
+#[test]
+fn execute_action_sc_call_sync_back_transfers_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let owner_address = setup.owner.clone();
+    let sc_address = setup.sc_wrapper.address_ref().clone();
+    let mock_address = setup.mock_sc_wrapper.address_ref().clone();
+
+    // the contract holds tokens in the mock, as a registered user
+    setup
+        .b_mock
+        .set_esdt_balance(&owner_address, TOKEN_ID, &rust_biguint!(1_000));
+    setup
+        .b_mock
+        .execute_tx(
+            &owner_address,
+            &setup.mock_sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.register_user(
+                    managed_address!(&sc_address),
+                    Signature::new_from_bytes(EMPTY_SIG),
+                );
+            },
+        )
+        .assert_ok();
+    setup
+        .b_mock
+        .execute_esdt_transfer(
+            &owner_address,
+            &setup.mock_sc_wrapper,
+            TOKEN_ID,
+            0,
+            &rust_biguint!(1_000),
+            |sc| {
+                sc.deposit_for_user(managed_address!(&sc_address));
+            },
+        )
+        .assert_ok();
+
+    // both actions withdraw from the mock, which sends the tokens back
+    setup
+        .b_mock
+        .execute_tx(
+            &setup.first_user,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut actions = MultiValueEncoded::new();
+                for user_nonce in 0..2u64 {
+                    let withdrawn_payments =
+                        PaymentsVec::<DebugApi>::from_single_item(EsdtTokenPayment::new(
+                            managed_token_id!(TOKEN_ID),
+                            0,
+                            managed_biguint!(100),
+                        ));
+                    let mut serialized_payments = ManagedBuffer::new();
+                    let _ = withdrawn_payments.top_encode(&mut serialized_payments);
+
+                    actions.push(
+                        (
+                            GeneralActionData {
+                                call_type: CallType::Sync,
+                                dest_address: managed_address!(&mock_address),
+                                payments: ManagedVec::new(),
+                                opt_execution: Some(ScExecutionData {
+                                    endpoint_name: managed_buffer!(b"withdraw"),
+                                    args: ManagedVec::from_single_item(serialized_payments),
+                                    gas_limit: 10_000,
+                                }),
+                                min_returns: ManagedVec::new(),
+                                opt_relayer_fee: None,
+                            },
+                            DEFAULT_NONCE_LANE,
+                            user_nonce,
+                            address_key_signature(),
+                        )
+                            .into(),
+                    );
+                }
+
+                sc.multi_action_for_user(managed_address!(&first_user_address), actions);
+            },
+        )
+        .assert_ok();
+
+    // each action is credited only with its own returns
+    let expected_first_user_tokens = [
+        TxTokenTransfer {
+            token_identifier: EGLD_TOKEN_ID.to_vec(),
+            nonce: 0,
+            value: rust_biguint!(FIRST_USER_EGLD_BALANCE),
+        },
+        TxTokenTransfer {
+            token_identifier: TOKEN_ID.to_vec(),
+            nonce: 0,
+            value: rust_biguint!(FIRST_USER_ESDT_BALANCE + 200),
+        },
+    ];
+    setup.check_user_tokens(&first_user_address, &expected_first_user_tokens);
+
+    let expected_sc_tokens_in_mock = [TxTokenTransfer {
+        token_identifier: TOKEN_ID.to_vec(),
+        nonce: 0,
+        value: rust_biguint!(800),
+    }];
+    setup.check_user_tokens_mock(&sc_address, &expected_sc_tokens_in_mock);
+}
+
+#[test]
+fn execute_action_relayer_fee_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+
+    // second user relays the action and collects the fee
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut actions = MultiValueEncoded::new();
+                actions.push(
+                    (
+                        GeneralActionData {
+                            call_type: CallType::Transfer,
+                            dest_address: managed_address!(&second_user_address),
+                            payments: ManagedVec::from_single_item(EsdtTokenPayment::new(
+                                managed_token_id!(EGLD_TOKEN_ID),
+                                0,
+                                managed_biguint!(100),
+                            )),
+                            opt_execution: None,
+                            min_returns: ManagedVec::new(),
+                            opt_relayer_fee: Some(RelayerFee {
+                                payment: EsdtTokenPayment::new(
+                                    managed_token_id!(TOKEN_ID),
+                                    0,
+                                    managed_biguint!(10),
+                                ),
+                                opt_recipient: None,
+                            }),
+                        },
+                        DEFAULT_NONCE_LANE,
+                        0u64,
+                        address_key_signature(),
+                    )
+                        .into(),
+                );
+
+                sc.multi_action_for_user(managed_address!(&first_user_address), actions);
+            },
+        )
+        .assert_ok();
+
+    // check first user tokens
+    let expected_first_user_tokens = [
+        TxTokenTransfer {
+            token_identifier: EGLD_TOKEN_ID.to_vec(),
+            nonce: 0,
+            value: rust_biguint!(FIRST_USER_EGLD_BALANCE - 100),
+        },
+        TxTokenTransfer {
+            token_identifier: TOKEN_ID.to_vec(),
+            nonce: 0,
+            value: rust_biguint!(FIRST_USER_ESDT_BALANCE - 10),
+        },
+    ];
+    setup.check_user_tokens(&first_user_address, &expected_first_user_tokens);
+
+    setup
+        .b_mock
+        .check_egld_balance(&second_user_address, &rust_biguint!(100));
+    setup
+        .b_mock
+        .check_esdt_balance(&second_user_address, TOKEN_ID, &rust_biguint!(10));
+}
+
+#[test]
+fn execute_action_batch_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut actions = MultiValueEncoded::new();
+                for token_id in [EGLD_TOKEN_ID, TOKEN_ID] {
+                    actions.push(GeneralActionData {
+                        call_type: CallType::Transfer,
+                        dest_address: managed_address!(&second_user_address),
+                        payments: ManagedVec::from_single_item(EsdtTokenPayment::new(
+                            managed_token_id!(token_id),
+                            0,
+                            managed_biguint!(100),
+                        )),
+                        opt_execution: None,
+                        min_returns: ManagedVec::new(),
+                        opt_relayer_fee: None,
+                    });
+                }
+
+                sc.multi_action_batch_for_user(
+                    managed_address!(&first_user_address),
+                    0,
+                    address_key_signature(),
+                    actions,
+                );
+
+                // one nonce for the whole batch
+                assert_eq!(
+                    sc.get_user_nonce(managed_address!(&first_user_address), OptionalValue::None),
+                    1
+                );
+            },
+        )
+        .assert_ok();
+
+    let expected_first_user_tokens = [
+        TxTokenTransfer {
+            token_identifier: EGLD_TOKEN_ID.to_vec(),
+            nonce: 0,
+            value: rust_biguint!(FIRST_USER_EGLD_BALANCE - 100),
+        },
+        TxTokenTransfer {
+            token_identifier: TOKEN_ID.to_vec(),
+            nonce: 0,
+            value: rust_biguint!(FIRST_USER_ESDT_BALANCE - 100),
+        },
+    ];
+    setup.check_user_tokens(&first_user_address, &expected_first_user_tokens);
+
+    setup
+        .b_mock
+        .check_egld_balance(&second_user_address, &rust_biguint!(100));
+    setup
+        .b_mock
+        .check_esdt_balance(&second_user_address, TOKEN_ID, &rust_biguint!(100));
+
+    // try replay the batch
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut actions = MultiValueEncoded::new();
+                actions.push(GeneralActionData {
+                    call_type: CallType::Transfer,
+                    dest_address: managed_address!(&second_user_address),
+                    payments: ManagedVec::from_single_item(EsdtTokenPayment::new(
+                        managed_token_id!(EGLD_TOKEN_ID),
+                        0,
+                        managed_biguint!(100),
+                    )),
+                    opt_execution: None,
+                    min_returns: ManagedVec::new(),
+                    opt_relayer_fee: None,
+                });
+
+                sc.multi_action_batch_for_user(
+                    managed_address!(&first_user_address),
+                    0,
+                    address_key_signature(),
+                    actions,
+                );
+            },
+        )
+        .assert_user_error("Invalid user nonce");
+}
+
+#[test]
+fn nonce_lanes_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+    let transfer = |setup: &mut AbstractionSetup<_>, nonce_lane: u64, user_nonce: u64| {
+        setup.b_mock.execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut actions = MultiValueEncoded::new();
+                actions.push(
+                    (
+                        GeneralActionData {
+                            call_type: CallType::Transfer,
+                            dest_address: managed_address!(&second_user_address),
+                            payments: ManagedVec::from_single_item(EsdtTokenPayment::new(
+                                managed_token_id!(EGLD_TOKEN_ID),
+                                0,
+                                managed_biguint!(100),
+                            )),
+                            opt_execution: None,
+                            min_returns: ManagedVec::new(),
+                            opt_relayer_fee: None,
+                        },
+                        nonce_lane,
+                        user_nonce,
+                        address_key_signature(),
+                    )
+                        .into(),
+                );
+
+                sc.multi_action_for_user(managed_address!(&first_user_address), actions);
+            },
+        )
+    };
+
+    // lanes are independent of each other
+    transfer(&mut setup, 7, 0).assert_ok();
+    transfer(&mut setup, 7, 1).assert_ok();
+    transfer(&mut setup, DEFAULT_NONCE_LANE, 0).assert_ok();
+
+    // try replay on the same lane
+    transfer(&mut setup, 7, 1).assert_user_error("Invalid user nonce");
+
+    setup
+        .b_mock
+        .execute_query(&setup.sc_wrapper, |sc| {
+            let user_address = managed_address!(&first_user_address);
+            assert_eq!(
+                sc.get_user_nonce(user_address.clone(), OptionalValue::None),
+                1
+            );
+            assert_eq!(
+                sc.get_user_nonce(user_address.clone(), OptionalValue::Some(7)),
+                2
+            );
+            assert_eq!(sc.get_user_nonce(user_address, OptionalValue::Some(8)), 0);
+        })
+        .assert_ok();
+
+    setup
+        .b_mock
+        .check_egld_balance(&second_user_address, &rust_biguint!(300));
+}
+
+#[test]
+fn invalidate_nonces_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+
+    // try invalidate for another user without signature
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.invalidate_nonces(
+                    managed_address!(&first_user_address),
+                    DEFAULT_NONCE_LANE,
+                    5,
+                    OptionalValue::None,
+                );
+            },
+        )
+        .assert_user_error("Signature required");
+
+    // user invalidates directly
+    setup
+        .b_mock
+        .execute_tx(
+            &first_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.invalidate_nonces(
+                    managed_address!(&first_user_address),
+                    DEFAULT_NONCE_LANE,
+                    5,
+                    OptionalValue::None,
+                );
+            },
+        )
+        .assert_ok();
+
+    // try use an invalidated nonce
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut actions = MultiValueEncoded::new();
+                actions.push(
+                    (
+                        GeneralActionData {
+                            call_type: CallType::Transfer,
+                            dest_address: managed_address!(&second_user_address),
+                            payments: ManagedVec::from_single_item(EsdtTokenPayment::new(
+                                managed_token_id!(EGLD_TOKEN_ID),
+                                0,
+                                managed_biguint!(100),
+                            )),
+                            opt_execution: None,
+                            min_returns: ManagedVec::new(),
+                            opt_relayer_fee: None,
+                        },
+                        DEFAULT_NONCE_LANE,
+                        0u64,
+                        address_key_signature(),
+                    )
+                        .into(),
+                );
+
+                sc.multi_action_for_user(managed_address!(&first_user_address), actions);
+            },
+        )
+        .assert_user_error("Invalid user nonce");
+
+    // relayed invalidation of another lane
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.invalidate_nonces(
+                    managed_address!(&first_user_address),
+                    7,
+                    3,
+                    OptionalValue::Some((5, address_key_signature()).into()),
+                );
+            },
+        )
+        .assert_ok();
+
+    // try move a nonce backwards
+    setup
+        .b_mock
+        .execute_tx(
+            &first_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.invalidate_nonces(
+                    managed_address!(&first_user_address),
+                    7,
+                    2,
+                    OptionalValue::None,
+                );
+            },
+        )
+        .assert_user_error("Invalid nonce");
+
+    setup
+        .b_mock
+        .execute_query(&setup.sc_wrapper, |sc| {
+            let user_address = managed_address!(&first_user_address);
+            assert_eq!(
+                sc.get_user_nonce(user_address.clone(), OptionalValue::None),
+                6
+            );
+            assert_eq!(sc.get_user_nonce(user_address, OptionalValue::Some(7)), 3);
+        })
+        .assert_ok();
+}
+
+#[test]
+fn atomic_batch_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+    let mock_address = setup.mock_sc_wrapper.address_ref().clone();
+    let run_batch = |setup: &mut AbstractionSetup<_>, call_type: CallType, min_returns: u64| {
+        setup.b_mock.execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut actions = MultiValueEncoded::new();
+                actions.push(GeneralActionData {
+                    call_type: CallType::Transfer,
+                    dest_address: managed_address!(&second_user_address),
+                    payments: ManagedVec::from_single_item(EsdtTokenPayment::new(
+                        managed_token_id!(EGLD_TOKEN_ID),
+                        0,
+                        managed_biguint!(100),
+                    )),
+                    opt_execution: None,
+                    min_returns: ManagedVec::new(),
+                    opt_relayer_fee: None,
+                });
+
+                let mut args = ManagedVec::new();
+                args.push(ManagedBuffer::new_from_bytes(
+                    ManagedAddress::<DebugApi>::from_address(&second_user_address)
+                        .to_byte_array()
+                        .as_slice(),
+                ));
+                let mut min_returns_vec = ManagedVec::new();
+                if min_returns > 0 {
+                    min_returns_vec.push(EsdtTokenPayment::new(
+                        managed_token_id!(TOKEN_ID),
+                        0,
+                        managed_biguint!(min_returns),
+                    ));
+                }
+                actions.push(GeneralActionData {
+                    call_type,
+                    dest_address: managed_address!(&mock_address),
+                    payments: ManagedVec::from_single_item(EsdtTokenPayment::new(
+                        managed_token_id!(TOKEN_ID),
+                        0,
+                        managed_biguint!(100),
+                    )),
+                    opt_execution: Some(ScExecutionData {
+                        endpoint_name: managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+                        args,
+                        gas_limit: 10_000,
+                    }),
+                    min_returns: min_returns_vec,
+                    opt_relayer_fee: None,
+                });
+
+                sc.atomic_multi_action_batch_for_user(
+                    managed_address!(&first_user_address),
+                    0,
+                    address_key_signature(),
+                    actions,
+                );
+            },
+        )
+    };
+
+    // try async action in atomic batch
+    run_batch(&mut setup, CallType::Async, 0)
+        .assert_user_error("Async actions not allowed in atomic batch");
+
+    // failed min returns check reverts the whole batch, including the transfer
+    run_batch(&mut setup, CallType::Sync, 50).assert_user_error("Min returns not met");
+
+    setup
+        .b_mock
+        .check_egld_balance(&second_user_address, &rust_biguint!(0));
+
+    let expected_first_user_tokens = [
+        TxTokenTransfer {
+            token_identifier: EGLD_TOKEN_ID.to_vec(),
+            nonce: 0,
+            value: rust_biguint!(FIRST_USER_EGLD_BALANCE),
+        },
+        TxTokenTransfer {
+            token_identifier: TOKEN_ID.to_vec(),
+            nonce: 0,
+            value: rust_biguint!(FIRST_USER_ESDT_BALANCE),
+        },
+    ];
+    setup.check_user_tokens(&first_user_address, &expected_first_user_tokens);
+
+    run_batch(&mut setup, CallType::Sync, 0).assert_ok();
+
+    setup
+        .b_mock
+        .check_egld_balance(&second_user_address, &rust_biguint!(100));
+}
@@ -0,0 +1,398 @@
+pub mod acc_abstraction_setup;
+
+use acc_abstraction_setup::*;
+use account_abstraction::{
+    common::common_types::{
+        CallType, GeneralActionData, ScExecutionData, DEFAULT_NONCE_LANE, EGLD_TOKEN_ID,
+    },
+    user_actions::paymaster::PaymasterModule,
+};
+use multiversx_sc::types::{
+    Address, EsdtTokenPayment, ManagedAddress, ManagedBuffer, ManagedVec, MultiValueEncoded,
+};
+use multiversx_sc_scenario::{
+    imports::TxTokenTransfer, managed_address, managed_biguint, managed_buffer, managed_token_id,
+    rust_biguint, DebugApi,
+};
+
+pub const PAYMASTER_BUDGET: u64 = 1_000;
+pub const FEE_PER_ACTION: u64 = 10;
+
+#[test]
+fn sponsored_action_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+    let paymaster_address = setup.owner.clone();
+    let mock_address = setup.mock_sc_wrapper.address_ref().clone();
+    setup
+        .b_mock
+        .set_egld_balance(&paymaster_address, &rust_biguint!(PAYMASTER_BUDGET));
+
+    // deposit budget and configure policy
+    setup
+        .b_mock
+        .execute_tx(
+            &paymaster_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(PAYMASTER_BUDGET),
+            |sc| {
+                sc.deposit_paymaster_budget();
+                sc.set_paymaster_policy(
+                    EsdtTokenPayment::new(
+                        managed_token_id!(EGLD_TOKEN_ID),
+                        0,
+                        managed_biguint!(FEE_PER_ACTION),
+                    ),
+                    managed_biguint!(FEE_PER_ACTION),
+                    managed_biguint!(FEE_PER_ACTION * 10),
+                    100,
+                );
+            },
+        )
+        .assert_ok();
+
+    // try relay an action that is not sponsored
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut actions = MultiValueEncoded::new();
+                let mut args = ManagedVec::new();
+                args.push(ManagedBuffer::new_from_bytes(
+                    ManagedAddress::<DebugApi>::from_address(&second_user_address)
+                        .to_byte_array()
+                        .as_slice(),
+                ));
+
+                actions.push(
+                    (
+                        GeneralActionData {
+                            call_type: CallType::Async,
+                            dest_address: managed_address!(&mock_address),
+                            payments: ManagedVec::from_single_item(EsdtTokenPayment::new(
+                                managed_token_id!(EGLD_TOKEN_ID),
+                                0,
+                                managed_biguint!(100),
+                            )),
+                            opt_execution: Some(ScExecutionData {
+                                endpoint_name: managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+                                args,
+                                gas_limit: 10_000,
+                            }),
+                            min_returns: ManagedVec::new(),
+                            opt_relayer_fee: None,
+                        },
+                        DEFAULT_NONCE_LANE,
+                        0u64,
+                        address_key_signature(),
+                    )
+                        .into(),
+                );
+
+                sc.sponsored_multi_action_for_user(
+                    managed_address!(&paymaster_address),
+                    managed_address!(&first_user_address),
+                    actions,
+                );
+            },
+        )
+        .assert_user_error("Action not sponsored");
+
+    setup
+        .b_mock
+        .execute_tx(
+            &paymaster_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut action_types = MultiValueEncoded::new();
+                action_types.push(
+                    (
+                        managed_address!(&mock_address),
+                        managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+                    )
+                        .into(),
+                );
+
+                sc.add_sponsored_actions(action_types);
+            },
+        )
+        .assert_ok();
+
+    // relay sponsored action
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut actions = MultiValueEncoded::new();
+                let mut args = ManagedVec::new();
+                args.push(ManagedBuffer::new_from_bytes(
+                    ManagedAddress::<DebugApi>::from_address(&second_user_address)
+                        .to_byte_array()
+                        .as_slice(),
+                ));
+
+                actions.push(
+                    (
+                        GeneralActionData {
+                            call_type: CallType::Async,
+                            dest_address: managed_address!(&mock_address),
+                            payments: ManagedVec::from_single_item(EsdtTokenPayment::new(
+                                managed_token_id!(EGLD_TOKEN_ID),
+                                0,
+                                managed_biguint!(100),
+                            )),
+                            opt_execution: Some(ScExecutionData {
+                                endpoint_name: managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+                                args,
+                                gas_limit: 10_000,
+                            }),
+                            min_returns: ManagedVec::new(),
+                            opt_relayer_fee: None,
+                        },
+                        DEFAULT_NONCE_LANE,
+                        0u64,
+                        address_key_signature(),
+                    )
+                        .into(),
+                );
+
+                sc.sponsored_multi_action_for_user(
+                    managed_address!(&paymaster_address),
+                    managed_address!(&first_user_address),
+                    actions,
+                );
+            },
+        )
+        .assert_ok();
+
+    // user only paid the action payments
+    let expected_first_user_tokens = [
+        TxTokenTransfer {
+            token_identifier: EGLD_TOKEN_ID.to_vec(),
+            nonce: 0,
+            value: rust_biguint!(FIRST_USER_EGLD_BALANCE - 100),
+        },
+        TxTokenTransfer {
+            token_identifier: TOKEN_ID.to_vec(),
+            nonce: 0,
+            value: rust_biguint!(FIRST_USER_ESDT_BALANCE),
+        },
+    ];
+    setup.check_user_tokens(&first_user_address, &expected_first_user_tokens);
+
+    // relayer was reimbursed by the paymaster
+    setup
+        .b_mock
+        .check_egld_balance(&second_user_address, &rust_biguint!(FEE_PER_ACTION));
+    setup
+        .b_mock
+        .execute_query(&setup.sc_wrapper, |sc| {
+            let budget = sc.get_paymaster_budget(managed_address!(&paymaster_address));
+            assert_eq!(
+                budget,
+                ManagedVec::from_single_item(EsdtTokenPayment::new(
+                    managed_token_id!(EGLD_TOKEN_ID),
+                    0,
+                    managed_biguint!(PAYMASTER_BUDGET - FEE_PER_ACTION),
+                ))
+            );
+        })
+        .assert_ok();
+
+    // try exceed the per-user cap in the same period
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut actions = MultiValueEncoded::new();
+                let mut args = ManagedVec::new();
+                args.push(ManagedBuffer::new_from_bytes(
+                    ManagedAddress::<DebugApi>::from_address(&second_user_address)
+                        .to_byte_array()
+                        .as_slice(),
+                ));
+
+                actions.push(
+                    (
+                        GeneralActionData {
+                            call_type: CallType::Async,
+                            dest_address: managed_address!(&mock_address),
+                            payments: ManagedVec::from_single_item(EsdtTokenPayment::new(
+                                managed_token_id!(EGLD_TOKEN_ID),
+                                0,
+                                managed_biguint!(100),
+                            )),
+                            opt_execution: Some(ScExecutionData {
+                                endpoint_name: managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+                                args,
+                                gas_limit: 10_000,
+                            }),
+                            min_returns: ManagedVec::new(),
+                            opt_relayer_fee: None,
+                        },
+                        DEFAULT_NONCE_LANE,
+                        1u64,
+                        address_key_signature(),
+                    )
+                        .into(),
+                );
+
+                sc.sponsored_multi_action_for_user(
+                    managed_address!(&paymaster_address),
+                    managed_address!(&first_user_address),
+                    actions,
+                );
+            },
+        )
+        .assert_user_error("Paymaster cap exceeded");
+}
+
+#[test]
+fn sponsored_async_action_failure_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+    let paymaster_address = setup.owner.clone();
+    let mock_address = setup.mock_sc_wrapper.address_ref().clone();
+    setup
+        .b_mock
+        .set_egld_balance(&paymaster_address, &rust_biguint!(PAYMASTER_BUDGET));
+
+    setup
+        .b_mock
+        .execute_tx(
+            &paymaster_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(PAYMASTER_BUDGET),
+            |sc| {
+                sc.deposit_paymaster_budget();
+                sc.set_paymaster_policy(
+                    EsdtTokenPayment::new(
+                        managed_token_id!(EGLD_TOKEN_ID),
+                        0,
+                        managed_biguint!(FEE_PER_ACTION),
+                    ),
+                    managed_biguint!(FEE_PER_ACTION),
+                    managed_biguint!(FEE_PER_ACTION * 10),
+                    100,
+                );
+
+                let mut action_types = MultiValueEncoded::new();
+                action_types.push(
+                    (
+                        managed_address!(&mock_address),
+                        managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+                    )
+                        .into(),
+                );
+                sc.add_sponsored_actions(action_types);
+            },
+        )
+        .assert_ok();
+
+    let deposit_for_user =
+        |setup: &mut AbstractionSetup<_>, receiver: &Address, user_nonce: u64| {
+            setup.b_mock.execute_tx(
+                &second_user_address,
+                &setup.sc_wrapper,
+                &rust_biguint!(0),
+                |sc| {
+                    let mut actions = MultiValueEncoded::new();
+                    let mut args = ManagedVec::new();
+                    args.push(ManagedBuffer::new_from_bytes(
+                        ManagedAddress::<DebugApi>::from_address(receiver)
+                            .to_byte_array()
+                            .as_slice(),
+                    ));
+
+                    actions.push(
+                        (
+                            GeneralActionData {
+                                call_type: CallType::Async,
+                                dest_address: managed_address!(&mock_address),
+                                payments: ManagedVec::from_single_item(EsdtTokenPayment::new(
+                                    managed_token_id!(EGLD_TOKEN_ID),
+                                    0,
+                                    managed_biguint!(100),
+                                )),
+                                opt_execution: Some(ScExecutionData {
+                                    endpoint_name: managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+                                    args,
+                                    gas_limit: 10_000,
+                                }),
+                                min_returns: ManagedVec::new(),
+                                opt_relayer_fee: None,
+                            },
+                            DEFAULT_NONCE_LANE,
+                            user_nonce,
+                            address_key_signature(),
+                        )
+                            .into(),
+                    );
+
+                    sc.sponsored_multi_action_for_user(
+                        managed_address!(&paymaster_address),
+                        managed_address!(&first_user_address),
+                        actions,
+                    );
+                },
+            )
+        };
+
+    // the first user is not registered in the mock, so the async call fails
+    deposit_for_user(&mut setup, &first_user_address, 0).assert_ok();
+
+    // user was refunded
+    let expected_first_user_tokens = [
+        TxTokenTransfer {
+            token_identifier: EGLD_TOKEN_ID.to_vec(),
+            nonce: 0,
+            value: rust_biguint!(FIRST_USER_EGLD_BALANCE),
+        },
+        TxTokenTransfer {
+            token_identifier: TOKEN_ID.to_vec(),
+            nonce: 0,
+            value: rust_biguint!(FIRST_USER_ESDT_BALANCE),
+        },
+    ];
+    setup.check_user_tokens(&first_user_address, &expected_first_user_tokens);
+
+    // relayer was not paid, and the fee returned to the paymaster's budget
+    setup
+        .b_mock
+        .check_egld_balance(&second_user_address, &rust_biguint!(0));
+    setup
+        .b_mock
+        .execute_query(&setup.sc_wrapper, |sc| {
+            let budget = sc.get_paymaster_budget(managed_address!(&paymaster_address));
+            assert_eq!(
+                budget,
+                ManagedVec::from_single_item(EsdtTokenPayment::new(
+                    managed_token_id!(EGLD_TOKEN_ID),
+                    0,
+                    managed_biguint!(PAYMASTER_BUDGET),
+                ))
+            );
+        })
+        .assert_ok();
+
+    // the failed action no longer counts towards the user's cap of one action per period
+    deposit_for_user(&mut setup, &second_user_address, 1).assert_ok();
+
+    setup
+        .b_mock
+        .check_egld_balance(&second_user_address, &rust_biguint!(FEE_PER_ACTION));
+}
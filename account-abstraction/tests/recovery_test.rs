@@ -0,0 +1,340 @@
+pub mod acc_abstraction_setup;
+
+use acc_abstraction_setup::*;
+use account_abstraction::{
+    common::{
+        common_types::{CallType, GeneralActionData, EGLD_TOKEN_ID},
+        keys::KeysModule,
+        recovery::RecoveryModule,
+        sessions::{SessionKey, SessionsModule},
+        signature::{KeyType, SigningKey},
+        users::UsersModule,
+    },
+    user_actions::{execution::ExecutionModule, whitelist_actions::WhitelistAction},
+};
+use multiversx_sc::{
+    imports::OptionalValue,
+    types::{EsdtTokenPayment, ManagedBuffer, ManagedVec, MultiValueEncoded},
+};
+use multiversx_sc_scenario::{
+    managed_address, managed_biguint, managed_token_id, rust_biguint, DebugApi,
+};
+
+pub static RECOVERY_KEY: &[u8; 32] = &[3u8; 32];
+pub static SESSION_KEY: &[u8; 32] = &[4u8; 32];
+pub const TIME_LOCK: u64 = 100;
+
+fn recovery_key() -> SigningKey<DebugApi> {
+    SigningKey {
+        key_type: KeyType::Ed25519,
+        public_key: ManagedBuffer::new_from_bytes(RECOVERY_KEY),
+    }
+}
+
+#[test]
+fn guardian_recovery_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+    let owner_address = setup.owner.clone();
+
+    // 2-of-2 guardians
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut guardians = MultiValueEncoded::new();
+                guardians.push(managed_address!(&owner_address));
+                guardians.push(managed_address!(&second_user_address));
+
+                sc.set_guardians(
+                    managed_address!(&first_user_address),
+                    2,
+                    TIME_LOCK,
+                    0,
+                    address_key_signature(),
+                    guardians,
+                );
+            },
+        )
+        .assert_ok();
+
+    // try propose from non-guardian
+    setup
+        .b_mock
+        .execute_tx(
+            &first_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.propose_recovery(managed_address!(&first_user_address), recovery_key());
+            },
+        )
+        .assert_user_error("Not a guardian");
+
+    setup
+        .b_mock
+        .execute_tx(&owner_address, &setup.sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.propose_recovery(managed_address!(&first_user_address), recovery_key());
+        })
+        .assert_ok();
+
+    // try execute with a single approval
+    setup
+        .b_mock
+        .execute_tx(&owner_address, &setup.sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.execute_recovery(managed_address!(&first_user_address));
+        })
+        .assert_user_error("Not enough approvals");
+
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.approve_recovery(managed_address!(&first_user_address));
+            },
+        )
+        .assert_ok();
+
+    // try execute during time-lock
+    setup
+        .b_mock
+        .execute_tx(&owner_address, &setup.sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.execute_recovery(managed_address!(&first_user_address));
+        })
+        .assert_user_error("Recovery time-locked");
+
+    // current key cancels the recovery
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.cancel_recovery(
+                    managed_address!(&first_user_address),
+                    1,
+                    address_key_signature(),
+                );
+
+                assert!(sc
+                    .get_pending_recovery(managed_address!(&first_user_address))
+                    .into_option()
+                    .is_none());
+            },
+        )
+        .assert_ok();
+
+    // propose and approve again
+    setup
+        .b_mock
+        .execute_tx(&owner_address, &setup.sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.propose_recovery(managed_address!(&first_user_address), recovery_key());
+        })
+        .assert_ok();
+
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.approve_recovery(managed_address!(&first_user_address));
+            },
+        )
+        .assert_ok();
+
+    // execute after time-lock
+    setup.b_mock.set_block_timestamp(TIME_LOCK);
+
+    setup
+        .b_mock
+        .execute_tx(&owner_address, &setup.sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.execute_recovery(managed_address!(&first_user_address));
+        })
+        .assert_ok();
+
+    // address key was replaced by the recovery key
+    setup
+        .b_mock
+        .execute_query(&setup.sc_wrapper, |sc| {
+            let keys: Vec<_> = sc
+                .get_user_keys(managed_address!(&first_user_address))
+                .into_iter()
+                .collect();
+            assert_eq!(keys.len(), 1);
+
+            let (_, signing_key) = keys.into_iter().next().unwrap().into_tuple();
+            assert_eq!(
+                signing_key.public_key,
+                ManagedBuffer::new_from_bytes(RECOVERY_KEY)
+            );
+        })
+        .assert_ok();
+}
+
+#[test]
+fn recovery_revokes_sessions_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+    let owner_address = setup.owner.clone();
+
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut guardians = MultiValueEncoded::new();
+                guardians.push(managed_address!(&owner_address));
+                guardians.push(managed_address!(&second_user_address));
+
+                sc.set_guardians(
+                    managed_address!(&first_user_address),
+                    2,
+                    TIME_LOCK,
+                    0,
+                    address_key_signature(),
+                    guardians,
+                );
+            },
+        )
+        .assert_ok();
+
+    // session authorized by the lost key
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let _ = sc.authorize_session_key(
+                    managed_address!(&first_user_address),
+                    SessionKey {
+                        signing_key: SigningKey {
+                            key_type: KeyType::Ed25519,
+                            public_key: ManagedBuffer::new_from_bytes(SESSION_KEY),
+                        },
+                        allowed_actions: ManagedVec::from_single_item(WhitelistAction::new(
+                            managed_address!(&second_user_address),
+                            ManagedBuffer::new(),
+                        )),
+                        spending_caps: ManagedVec::from_single_item(EsdtTokenPayment::new(
+                            managed_token_id!(EGLD_TOKEN_ID),
+                            0,
+                            managed_biguint!(100),
+                        )),
+                        expires_at: TIME_LOCK * 10,
+                    },
+                    1,
+                    address_key_signature(),
+                );
+            },
+        )
+        .assert_ok();
+
+    setup
+        .b_mock
+        .execute_tx(&owner_address, &setup.sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.propose_recovery(managed_address!(&first_user_address), recovery_key());
+        })
+        .assert_ok();
+
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.approve_recovery(managed_address!(&first_user_address));
+            },
+        )
+        .assert_ok();
+
+    setup.b_mock.set_block_timestamp(TIME_LOCK);
+
+    setup
+        .b_mock
+        .execute_tx(&owner_address, &setup.sc_wrapper, &rust_biguint!(0), |sc| {
+            sc.execute_recovery(managed_address!(&first_user_address));
+        })
+        .assert_ok();
+
+    // try use the session after recovery
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut actions = MultiValueEncoded::new();
+                actions.push(
+                    (
+                        GeneralActionData {
+                            call_type: CallType::Transfer,
+                            dest_address: managed_address!(&second_user_address),
+                            payments: ManagedVec::from_single_item(EsdtTokenPayment::new(
+                                managed_token_id!(EGLD_TOKEN_ID),
+                                0,
+                                managed_biguint!(100),
+                            )),
+                            opt_execution: None,
+                            min_returns: ManagedVec::new(),
+                            opt_relayer_fee: None,
+                        },
+                        1u64,
+                        0u64,
+                        key_signatures(&[1]),
+                    )
+                        .into(),
+                );
+
+                sc.session_multi_action_for_user(managed_address!(&first_user_address), 1, actions);
+            },
+        )
+        .assert_user_error("Unknown session");
+
+    // try withdraw directly with the lost address key
+    setup
+        .b_mock
+        .execute_tx(
+            &first_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.withdraw(
+                    ManagedVec::from_single_item(EsdtTokenPayment::new(
+                        managed_token_id!(EGLD_TOKEN_ID),
+                        0,
+                        managed_biguint!(100),
+                    )),
+                    OptionalValue::None,
+                );
+            },
+        )
+        .assert_user_error("Address key not authorized");
+
+    setup
+        .b_mock
+        .execute_query(&setup.sc_wrapper, |sc| {
+            assert!(sc
+                .get_session_keys(managed_address!(&first_user_address))
+                .is_empty());
+        })
+        .assert_ok();
+}
@@ -0,0 +1,414 @@
+pub mod acc_abstraction_setup;
+
+use acc_abstraction_setup::*;
+use account_abstraction::{
+    common::{
+        common_types::{
+            ActionMultiValue, CallType, GeneralActionData, ScExecutionData, DEFAULT_NONCE_LANE,
+            EGLD_TOKEN_ID,
+        },
+        sessions::{SessionKey, SessionsModule, MAX_USER_SESSIONS},
+        signature::{KeyType, SigningKey},
+    },
+    user_actions::{execution::ExecutionModule, whitelist_actions::WhitelistAction},
+};
+use multiversx_sc::types::{
+    Address, EsdtTokenPayment, ManagedAddress, ManagedBuffer, ManagedVec, MultiValueEncoded,
+};
+use multiversx_sc_scenario::{
+    imports::TxTokenTransfer, managed_address, managed_biguint, managed_buffer, managed_token_id,
+    rust_biguint, DebugApi,
+};
+
+pub static SESSION_KEY: &[u8; 32] = &[4u8; 32];
+pub const SESSION_CAP: u64 = 150;
+pub const SESSION_EXPIRY: u64 = 100;
+pub const SESSION_NONCE_LANE: u64 = 1;
+
+fn session_transfer(
+    receiver: &Address,
+    amount: u64,
+    nonce_lane: u64,
+    user_nonce: u64,
+) -> MultiValueEncoded<DebugApi, ActionMultiValue<DebugApi>> {
+    let mut actions = MultiValueEncoded::new();
+    actions.push(
+        (
+            GeneralActionData {
+                call_type: CallType::Transfer,
+                dest_address: managed_address!(receiver),
+                payments: ManagedVec::from_single_item(EsdtTokenPayment::new(
+                    managed_token_id!(EGLD_TOKEN_ID),
+                    0,
+                    managed_biguint!(amount),
+                )),
+                opt_execution: None,
+                min_returns: ManagedVec::new(),
+                opt_relayer_fee: None,
+            },
+            nonce_lane,
+            user_nonce,
+            key_signatures(&[1]),
+        )
+            .into(),
+    );
+
+    actions
+}
+
+#[test]
+fn session_key_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+    let owner_address = setup.owner.clone();
+
+    // allow EGLD transfers to second user only
+    setup
+        .b_mock
+        .execute_tx(
+            &first_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let session_id = sc.authorize_session_key(
+                    managed_address!(&first_user_address),
+                    SessionKey {
+                        signing_key: SigningKey {
+                            key_type: KeyType::Ed25519,
+                            public_key: ManagedBuffer::new_from_bytes(SESSION_KEY),
+                        },
+                        allowed_actions: ManagedVec::from_single_item(WhitelistAction::new(
+                            managed_address!(&second_user_address),
+                            ManagedBuffer::new(),
+                        )),
+                        spending_caps: ManagedVec::from_single_item(EsdtTokenPayment::new(
+                            managed_token_id!(EGLD_TOKEN_ID),
+                            0,
+                            managed_biguint!(SESSION_CAP),
+                        )),
+                        expires_at: SESSION_EXPIRY,
+                    },
+                    0,
+                    address_key_signature(),
+                );
+                assert_eq!(session_id, 1);
+            },
+        )
+        .assert_ok();
+
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.session_multi_action_for_user(
+                    managed_address!(&first_user_address),
+                    1,
+                    session_transfer(&second_user_address, 100, SESSION_NONCE_LANE, 0),
+                );
+            },
+        )
+        .assert_ok();
+
+    // try use the default nonce lane
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.session_multi_action_for_user(
+                    managed_address!(&first_user_address),
+                    1,
+                    session_transfer(&second_user_address, 10, DEFAULT_NONCE_LANE, 1),
+                );
+            },
+        )
+        .assert_user_error("Default nonce lane not allowed for sessions");
+
+    // try transfer to another receiver
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.session_multi_action_for_user(
+                    managed_address!(&first_user_address),
+                    1,
+                    session_transfer(&owner_address, 10, SESSION_NONCE_LANE, 1),
+                );
+            },
+        )
+        .assert_user_error("Action not allowed for session");
+
+    // try exceed the session spending cap
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.session_multi_action_for_user(
+                    managed_address!(&first_user_address),
+                    1,
+                    session_transfer(&second_user_address, 100, SESSION_NONCE_LANE, 1),
+                );
+            },
+        )
+        .assert_user_error("Session spending cap exceeded");
+
+    // try use session after expiry
+    setup.b_mock.set_block_timestamp(SESSION_EXPIRY);
+
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.session_multi_action_for_user(
+                    managed_address!(&first_user_address),
+                    1,
+                    session_transfer(&second_user_address, 10, SESSION_NONCE_LANE, 1),
+                );
+            },
+        )
+        .assert_user_error("Session expired");
+
+    // check first user tokens
+    let expected_first_user_tokens = [
+        TxTokenTransfer {
+            token_identifier: EGLD_TOKEN_ID.to_vec(),
+            nonce: 0,
+            value: rust_biguint!(FIRST_USER_EGLD_BALANCE - 100),
+        },
+        TxTokenTransfer {
+            token_identifier: TOKEN_ID.to_vec(),
+            nonce: 0,
+            value: rust_biguint!(FIRST_USER_ESDT_BALANCE),
+        },
+    ];
+    setup.check_user_tokens(&first_user_address, &expected_first_user_tokens);
+
+    setup
+        .b_mock
+        .check_egld_balance(&second_user_address, &rust_biguint!(100));
+}
+
+#[test]
+fn session_caps_restore_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+    let mock_address = setup.mock_sc_wrapper.address_ref().clone();
+
+    setup
+        .b_mock
+        .execute_tx(
+            &first_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let _ = sc.authorize_session_key(
+                    managed_address!(&first_user_address),
+                    SessionKey {
+                        signing_key: SigningKey {
+                            key_type: KeyType::Ed25519,
+                            public_key: ManagedBuffer::new_from_bytes(SESSION_KEY),
+                        },
+                        allowed_actions: ManagedVec::from_single_item(WhitelistAction::new(
+                            managed_address!(&mock_address),
+                            managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+                        )),
+                        spending_caps: ManagedVec::from_single_item(EsdtTokenPayment::new(
+                            managed_token_id!(EGLD_TOKEN_ID),
+                            0,
+                            managed_biguint!(SESSION_CAP),
+                        )),
+                        expires_at: SESSION_EXPIRY,
+                    },
+                    0,
+                    address_key_signature(),
+                );
+            },
+        )
+        .assert_ok();
+
+    // the first user is not registered in the mock, so the async call fails
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut args = ManagedVec::new();
+                args.push(ManagedBuffer::new_from_bytes(
+                    ManagedAddress::<DebugApi>::from_address(&first_user_address)
+                        .to_byte_array()
+                        .as_slice(),
+                ));
+
+                let mut actions = MultiValueEncoded::new();
+                actions.push(
+                    (
+                        GeneralActionData {
+                            call_type: CallType::Async,
+                            dest_address: managed_address!(&mock_address),
+                            payments: ManagedVec::from_single_item(EsdtTokenPayment::new(
+                                managed_token_id!(EGLD_TOKEN_ID),
+                                0,
+                                managed_biguint!(100),
+                            )),
+                            opt_execution: Some(ScExecutionData {
+                                endpoint_name: managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+                                args,
+                                gas_limit: 10_000,
+                            }),
+                            min_returns: ManagedVec::new(),
+                            opt_relayer_fee: None,
+                        },
+                        SESSION_NONCE_LANE,
+                        0u64,
+                        key_signatures(&[1]),
+                    )
+                        .into(),
+                );
+
+                sc.session_multi_action_for_user(managed_address!(&first_user_address), 1, actions);
+            },
+        )
+        .assert_ok();
+
+    setup
+        .b_mock
+        .execute_query(&setup.sc_wrapper, |sc| {
+            let (_, _, remaining_caps) = sc
+                .get_session_keys(managed_address!(&first_user_address))
+                .into_iter()
+                .next()
+                .unwrap()
+                .into_tuple();
+            assert_eq!(remaining_caps.len(), 1);
+            assert_eq!(remaining_caps.get(0).amount, managed_biguint!(SESSION_CAP));
+        })
+        .assert_ok();
+
+    let expected_first_user_tokens = [
+        TxTokenTransfer {
+            token_identifier: EGLD_TOKEN_ID.to_vec(),
+            nonce: 0,
+            value: rust_biguint!(FIRST_USER_EGLD_BALANCE),
+        },
+        TxTokenTransfer {
+            token_identifier: TOKEN_ID.to_vec(),
+            nonce: 0,
+            value: rust_biguint!(FIRST_USER_ESDT_BALANCE),
+        },
+    ];
+    setup.check_user_tokens(&first_user_address, &expected_first_user_tokens);
+}
+
+#[test]
+fn expired_sessions_limit_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+
+    let authorize_session =
+        |sc: &account_abstraction::ContractObj<DebugApi>, expires_at: u64, user_nonce: u64| {
+            sc.authorize_session_key(
+                managed_address!(&first_user_address),
+                SessionKey {
+                    signing_key: SigningKey {
+                        key_type: KeyType::Ed25519,
+                        public_key: ManagedBuffer::new_from_bytes(SESSION_KEY),
+                    },
+                    allowed_actions: ManagedVec::from_single_item(WhitelistAction::new(
+                        managed_address!(&second_user_address),
+                        ManagedBuffer::new(),
+                    )),
+                    spending_caps: ManagedVec::new(),
+                    expires_at,
+                },
+                user_nonce,
+                address_key_signature(),
+            )
+        };
+
+    for user_nonce in 0..MAX_USER_SESSIONS as u64 {
+        setup
+            .b_mock
+            .execute_tx(
+                &second_user_address,
+                &setup.sc_wrapper,
+                &rust_biguint!(0),
+                |sc| {
+                    let _ = authorize_session(&sc, SESSION_EXPIRY, user_nonce);
+                },
+            )
+            .assert_ok();
+    }
+
+    // try authorize one more session
+    let next_nonce = MAX_USER_SESSIONS as u64;
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let _ = authorize_session(&sc, SESSION_EXPIRY * 2, next_nonce);
+            },
+        )
+        .assert_user_error("Too many sessions");
+
+    // expired sessions don't count towards the limit
+    setup.b_mock.set_block_timestamp(SESSION_EXPIRY);
+
+    setup
+        .b_mock
+        .execute_query(&setup.sc_wrapper, |sc| {
+            assert!(sc
+                .get_session_keys(managed_address!(&first_user_address))
+                .is_empty());
+        })
+        .assert_ok();
+
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let session_id = authorize_session(&sc, SESSION_EXPIRY * 2, next_nonce);
+                assert_eq!(session_id, MAX_USER_SESSIONS as u32 + 1);
+            },
+        )
+        .assert_ok();
+
+    setup
+        .b_mock
+        .execute_query(&setup.sc_wrapper, |sc| {
+            let session_ids: Vec<_> = sc
+                .get_session_keys(managed_address!(&first_user_address))
+                .into_iter()
+                .map(|multi_value| multi_value.into_tuple().0)
+                .collect();
+            assert_eq!(session_ids, [MAX_USER_SESSIONS as u32 + 1]);
+        })
+        .assert_ok();
+}
@@ -0,0 +1,652 @@
+pub mod acc_abstraction_setup;
+
+use acc_abstraction_setup::*;
+use account_abstraction::{
+    common::{
+        common_types::{
+            ActionMultiValue, CallType, GeneralActionData, ScExecutionData, SpendingLimit,
+            DEFAULT_NONCE_LANE, EGLD_TOKEN_ID, SPENDING_WINDOW_BUCKETS,
+        },
+        recovery::RecoveryModule,
+        spending_limits::{SpendingLimitsModule, LIMIT_CHANGE_TIME_LOCK},
+    },
+    user_actions::{
+        execution::ExecutionModule,
+        intents::{IntentConditions, IntentsModule},
+    },
+};
+use multiversx_sc::{
+    imports::OptionalValue,
+    types::{
+        Address, EsdtTokenPayment, ManagedAddress, ManagedBuffer, ManagedVec, MultiValueEncoded,
+    },
+};
+use multiversx_sc_scenario::{
+    managed_address, managed_biguint, managed_buffer, managed_token_id, rust_biguint, DebugApi,
+};
+
+pub const EGLD_LIMIT: u64 = 150;
+pub const RAISED_EGLD_LIMIT: u64 = 400;
+pub const LIMIT_WINDOW: u64 = 100;
+
+fn egld_transfer(
+    receiver: &Address,
+    amount: u64,
+    user_nonce: u64,
+) -> MultiValueEncoded<DebugApi, ActionMultiValue<DebugApi>> {
+    let mut actions = MultiValueEncoded::new();
+    actions.push(
+        (
+            GeneralActionData {
+                call_type: CallType::Transfer,
+                dest_address: managed_address!(receiver),
+                payments: ManagedVec::from_single_item(EsdtTokenPayment::new(
+                    managed_token_id!(EGLD_TOKEN_ID),
+                    0,
+                    managed_biguint!(amount),
+                )),
+                opt_execution: None,
+                min_returns: ManagedVec::new(),
+                opt_relayer_fee: None,
+            },
+            DEFAULT_NONCE_LANE,
+            user_nonce,
+            address_key_signature(),
+        )
+            .into(),
+    );
+
+    actions
+}
+
+#[test]
+fn spending_limit_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+
+    // new limits apply immediately
+    setup
+        .b_mock
+        .execute_tx(
+            &first_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.set_spending_limit(
+                    managed_address!(&first_user_address),
+                    managed_token_id!(EGLD_TOKEN_ID),
+                    Some(SpendingLimit {
+                        amount: managed_biguint!(EGLD_LIMIT),
+                        window: LIMIT_WINDOW,
+                    }),
+                    0,
+                    address_key_signature(),
+                );
+            },
+        )
+        .assert_ok();
+
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.multi_action_for_user(
+                    managed_address!(&first_user_address),
+                    egld_transfer(&second_user_address, 100, 1),
+                );
+
+                let remaining = sc.get_remaining_allowance(
+                    managed_address!(&first_user_address),
+                    managed_token_id!(EGLD_TOKEN_ID),
+                );
+                assert_eq!(
+                    remaining.into_option(),
+                    Some(managed_biguint!(EGLD_LIMIT - 100))
+                );
+            },
+        )
+        .assert_ok();
+
+    // try exceed limit
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.multi_action_for_user(
+                    managed_address!(&first_user_address),
+                    egld_transfer(&second_user_address, 100, 2),
+                );
+            },
+        )
+        .assert_user_error("Spending limit exceeded");
+
+    // raising the limit is time-locked
+    setup
+        .b_mock
+        .execute_tx(
+            &first_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.set_spending_limit(
+                    managed_address!(&first_user_address),
+                    managed_token_id!(EGLD_TOKEN_ID),
+                    Some(SpendingLimit {
+                        amount: managed_biguint!(RAISED_EGLD_LIMIT),
+                        window: LIMIT_WINDOW,
+                    }),
+                    2,
+                    address_key_signature(),
+                );
+            },
+        )
+        .assert_ok();
+
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.apply_spending_limit_change(
+                    managed_address!(&first_user_address),
+                    managed_token_id!(EGLD_TOKEN_ID),
+                );
+            },
+        )
+        .assert_user_error("Limit change time-locked");
+
+    setup.b_mock.set_block_timestamp(LIMIT_CHANGE_TIME_LOCK);
+
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.apply_spending_limit_change(
+                    managed_address!(&first_user_address),
+                    managed_token_id!(EGLD_TOKEN_ID),
+                );
+            },
+        )
+        .assert_ok();
+
+    // window elapsed and limit was raised
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.multi_action_for_user(
+                    managed_address!(&first_user_address),
+                    egld_transfer(&second_user_address, 300, 3),
+                );
+            },
+        )
+        .assert_ok();
+
+    setup
+        .b_mock
+        .check_egld_balance(&second_user_address, &rust_biguint!(400));
+}
+
+#[test]
+fn rolling_window_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+
+    setup
+        .b_mock
+        .execute_tx(
+            &first_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.set_spending_limit(
+                    managed_address!(&first_user_address),
+                    managed_token_id!(EGLD_TOKEN_ID),
+                    Some(SpendingLimit {
+                        amount: managed_biguint!(EGLD_LIMIT),
+                        window: LIMIT_WINDOW,
+                    }),
+                    0,
+                    address_key_signature(),
+                );
+            },
+        )
+        .assert_ok();
+
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.multi_action_for_user(
+                    managed_address!(&first_user_address),
+                    egld_transfer(&second_user_address, 100, 1),
+                );
+            },
+        )
+        .assert_ok();
+
+    setup.b_mock.set_block_timestamp(LIMIT_WINDOW / 2);
+
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.multi_action_for_user(
+                    managed_address!(&first_user_address),
+                    egld_transfer(&second_user_address, 50, 2),
+                );
+            },
+        )
+        .assert_ok();
+
+    // the first outflow still counts right after the window's length elapsed
+    setup.b_mock.set_block_timestamp(LIMIT_WINDOW);
+
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.multi_action_for_user(
+                    managed_address!(&first_user_address),
+                    egld_transfer(&second_user_address, 50, 3),
+                );
+            },
+        )
+        .assert_user_error("Spending limit exceeded");
+
+    // only the second outflow counts once the first one's bucket left the window
+    let bucket_len = LIMIT_WINDOW / SPENDING_WINDOW_BUCKETS;
+    setup.b_mock.set_block_timestamp(LIMIT_WINDOW + bucket_len);
+
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let remaining = sc.get_remaining_allowance(
+                    managed_address!(&first_user_address),
+                    managed_token_id!(EGLD_TOKEN_ID),
+                );
+                assert_eq!(
+                    remaining.into_option(),
+                    Some(managed_biguint!(EGLD_LIMIT - 50))
+                );
+
+                sc.multi_action_for_user(
+                    managed_address!(&first_user_address),
+                    egld_transfer(&second_user_address, 100, 3),
+                );
+            },
+        )
+        .assert_ok();
+
+    setup
+        .b_mock
+        .check_egld_balance(&second_user_address, &rust_biguint!(250));
+}
+
+#[test]
+fn failed_async_action_restores_allowance_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+    let mock_address = setup.mock_sc_wrapper.address_ref().clone();
+
+    setup
+        .b_mock
+        .execute_tx(
+            &first_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.set_spending_limit(
+                    managed_address!(&first_user_address),
+                    managed_token_id!(EGLD_TOKEN_ID),
+                    Some(SpendingLimit {
+                        amount: managed_biguint!(EGLD_LIMIT),
+                        window: LIMIT_WINDOW,
+                    }),
+                    0,
+                    address_key_signature(),
+                );
+            },
+        )
+        .assert_ok();
+
+    // the first user is not registered in the mock, so the async call fails
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut actions = MultiValueEncoded::new();
+                let mut args = ManagedVec::new();
+                args.push(ManagedBuffer::new_from_bytes(
+                    ManagedAddress::<DebugApi>::from_address(&first_user_address)
+                        .to_byte_array()
+                        .as_slice(),
+                ));
+
+                actions.push(
+                    (
+                        GeneralActionData {
+                            call_type: CallType::Async,
+                            dest_address: managed_address!(&mock_address),
+                            payments: ManagedVec::from_single_item(EsdtTokenPayment::new(
+                                managed_token_id!(EGLD_TOKEN_ID),
+                                0,
+                                managed_biguint!(100),
+                            )),
+                            opt_execution: Some(ScExecutionData {
+                                endpoint_name: managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+                                args,
+                                gas_limit: 10_000,
+                            }),
+                            min_returns: ManagedVec::new(),
+                            opt_relayer_fee: None,
+                        },
+                        DEFAULT_NONCE_LANE,
+                        1u64,
+                        address_key_signature(),
+                    )
+                        .into(),
+                );
+
+                sc.multi_action_for_user(managed_address!(&first_user_address), actions);
+            },
+        )
+        .assert_ok();
+
+    setup
+        .b_mock
+        .execute_query(&setup.sc_wrapper, |sc| {
+            let remaining = sc.get_remaining_allowance(
+                managed_address!(&first_user_address),
+                managed_token_id!(EGLD_TOKEN_ID),
+            );
+            assert_eq!(remaining.into_option(), Some(managed_biguint!(EGLD_LIMIT)));
+        })
+        .assert_ok();
+}
+
+#[test]
+fn removed_intents_restore_allowance_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+    let mock_address = setup.mock_sc_wrapper.address_ref().clone();
+
+    setup
+        .b_mock
+        .execute_tx(
+            &first_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.set_spending_limit(
+                    managed_address!(&first_user_address),
+                    managed_token_id!(EGLD_TOKEN_ID),
+                    Some(SpendingLimit {
+                        amount: managed_biguint!(EGLD_LIMIT),
+                        window: LIMIT_WINDOW,
+                    }),
+                    0,
+                    address_key_signature(),
+                );
+            },
+        )
+        .assert_ok();
+
+    // the second intent expires before the limit's window elapses
+    setup
+        .b_mock
+        .execute_tx(
+            &first_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut actions = MultiValueEncoded::new();
+                for (user_nonce, opt_valid_until) in [(1u64, None), (2u64, Some(LIMIT_WINDOW / 2))]
+                {
+                    let mut args = ManagedVec::new();
+                    args.push(ManagedBuffer::new_from_bytes(
+                        ManagedAddress::<DebugApi>::from_address(&second_user_address)
+                            .to_byte_array()
+                            .as_slice(),
+                    ));
+
+                    actions.push(
+                        (
+                            GeneralActionData {
+                                call_type: CallType::Async,
+                                dest_address: managed_address!(&mock_address),
+                                payments: ManagedVec::from_single_item(EsdtTokenPayment::new(
+                                    managed_token_id!(EGLD_TOKEN_ID),
+                                    0,
+                                    managed_biguint!(50),
+                                )),
+                                opt_execution: Some(ScExecutionData {
+                                    endpoint_name: managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+                                    args,
+                                    gas_limit: 10_000,
+                                }),
+                                min_returns: ManagedVec::new(),
+                                opt_relayer_fee: None,
+                            },
+                            IntentConditions {
+                                opt_valid_after: None,
+                                opt_valid_until,
+                                opt_predicate: None,
+                                opt_balance_predicate: None,
+                            },
+                            user_nonce,
+                            address_key_signature(),
+                        )
+                            .into(),
+                    );
+                }
+
+                sc.save_intents(managed_address!(&first_user_address), actions);
+
+                let remaining = sc.get_remaining_allowance(
+                    managed_address!(&first_user_address),
+                    managed_token_id!(EGLD_TOKEN_ID),
+                );
+                assert_eq!(
+                    remaining.into_option(),
+                    Some(managed_biguint!(EGLD_LIMIT - 100))
+                );
+            },
+        )
+        .assert_ok();
+
+    setup
+        .b_mock
+        .execute_tx(
+            &first_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.cancel_intent(
+                    managed_address!(&first_user_address),
+                    1,
+                    OptionalValue::None,
+                );
+
+                let remaining = sc.get_remaining_allowance(
+                    managed_address!(&first_user_address),
+                    managed_token_id!(EGLD_TOKEN_ID),
+                );
+                assert_eq!(
+                    remaining.into_option(),
+                    Some(managed_biguint!(EGLD_LIMIT - 50))
+                );
+            },
+        )
+        .assert_ok();
+
+    setup.b_mock.set_block_timestamp(LIMIT_WINDOW / 2 + 1);
+
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut intent_ids = MultiValueEncoded::new();
+                intent_ids.push(2);
+
+                sc.cleanup_expired_intents(managed_address!(&first_user_address), intent_ids);
+
+                let remaining = sc.get_remaining_allowance(
+                    managed_address!(&first_user_address),
+                    managed_token_id!(EGLD_TOKEN_ID),
+                );
+                assert_eq!(remaining.into_option(), Some(managed_biguint!(EGLD_LIMIT)));
+            },
+        )
+        .assert_ok();
+}
+
+#[test]
+fn limit_change_approvals_follow_guardians_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+    let owner_address = setup.owner.clone();
+    let mock_address = setup.mock_sc_wrapper.address_ref().clone();
+
+    let set_guardians = |setup: &mut AbstractionSetup<_>, guardians: [&Address; 2], user_nonce| {
+        setup.b_mock.execute_tx(
+            &first_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut guardians_arg = MultiValueEncoded::new();
+                for guardian in guardians {
+                    guardians_arg.push(managed_address!(guardian));
+                }
+
+                sc.set_guardians(
+                    managed_address!(&first_user_address),
+                    2,
+                    LIMIT_CHANGE_TIME_LOCK,
+                    user_nonce,
+                    address_key_signature(),
+                    guardians_arg,
+                );
+            },
+        )
+    };
+    let approve = |setup: &mut AbstractionSetup<_>, guardian: &Address| {
+        setup
+            .b_mock
+            .execute_tx(guardian, &setup.sc_wrapper, &rust_biguint!(0), |sc| {
+                sc.approve_spending_limit_change(
+                    managed_address!(&first_user_address),
+                    managed_token_id!(EGLD_TOKEN_ID),
+                );
+            })
+    };
+
+    set_guardians(&mut setup, [&owner_address, &second_user_address], 0).assert_ok();
+
+    for (user_nonce, amount) in [(1u64, EGLD_LIMIT), (2u64, RAISED_EGLD_LIMIT)] {
+        setup
+            .b_mock
+            .execute_tx(
+                &first_user_address,
+                &setup.sc_wrapper,
+                &rust_biguint!(0),
+                |sc| {
+                    sc.set_spending_limit(
+                        managed_address!(&first_user_address),
+                        managed_token_id!(EGLD_TOKEN_ID),
+                        Some(SpendingLimit {
+                            amount: managed_biguint!(amount),
+                            window: LIMIT_WINDOW,
+                        }),
+                        user_nonce,
+                        address_key_signature(),
+                    );
+                },
+            )
+            .assert_ok();
+    }
+
+    approve(&mut setup, &owner_address).assert_ok();
+
+    // the owner is no longer a guardian, so their approval no longer counts
+    set_guardians(&mut setup, [&second_user_address, &mock_address], 3).assert_ok();
+    approve(&mut setup, &second_user_address).assert_ok();
+
+    setup
+        .b_mock
+        .execute_query(&setup.sc_wrapper, |sc| {
+            let pending_change = sc
+                .get_pending_spending_limit_change(
+                    managed_address!(&first_user_address),
+                    managed_token_id!(EGLD_TOKEN_ID),
+                )
+                .into_option()
+                .unwrap();
+            assert_eq!(pending_change.approvals.len(), 1);
+
+            let limits: Vec<_> = sc
+                .get_spending_limits(managed_address!(&first_user_address))
+                .into_iter()
+                .map(|multi_value| multi_value.into_tuple().1)
+                .collect();
+            assert_eq!(limits[0].amount, managed_biguint!(EGLD_LIMIT));
+        })
+        .assert_ok();
+
+    approve(&mut setup, &mock_address).assert_ok();
+
+    setup
+        .b_mock
+        .execute_query(&setup.sc_wrapper, |sc| {
+            let limits: Vec<_> = sc
+                .get_spending_limits(managed_address!(&first_user_address))
+                .into_iter()
+                .map(|multi_value| multi_value.into_tuple().1)
+                .collect();
+            assert_eq!(limits[0].amount, managed_biguint!(RAISED_EGLD_LIMIT));
+        })
+        .assert_ok();
+}
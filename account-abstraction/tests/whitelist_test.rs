@@ -2,11 +2,21 @@ pub mod acc_abstraction_setup;
 
 use acc_abstraction_setup::*;
 use account_abstraction::{
-    common::common_types::EGLD_TOKEN_ID, user_actions::whitelist_actions::WhitelistActionsModule,
+    common::{common_types::EGLD_TOKEN_ID, users::UsersModule},
+    user_actions::{
+        views::ViewsModule,
+        whitelist_actions::{
+            ArgConstraint, ArgConstraintType, RateLimit, TokenAllowance, WhitelistAction,
+            WhitelistActionsModule, WhitelistLimits,
+        },
+        whitelist_storage::WhitelistStorageModule,
+    },
 };
 use multiversx_sc::{
     imports::OptionalValue,
-    types::{EsdtTokenPayment, ManagedAddress, ManagedBuffer, ManagedVec, MultiValueEncoded},
+    types::{
+        Address, EsdtTokenPayment, ManagedAddress, ManagedBuffer, ManagedVec, MultiValueEncoded,
+    },
 };
 use multiversx_sc_scenario::{
     imports::TxTokenTransfer, managed_address, managed_biguint, managed_buffer, managed_token_id,
@@ -58,11 +68,26 @@ fn whitelist_actions_test() {
             &setup.sc_wrapper,
             &rust_biguint!(0),
             |sc| {
+                let mut allowance = ManagedVec::new();
+                allowance.push(TokenAllowance {
+                    token_id: managed_token_id!(EGLD_TOKEN_ID),
+                    max_per_call: managed_biguint!(100),
+                    remaining_budget: managed_biguint!(100),
+                });
+                allowance.push(TokenAllowance {
+                    token_id: managed_token_id!(TOKEN_ID),
+                    max_per_call: managed_biguint!(200),
+                    remaining_budget: managed_biguint!(300),
+                });
+
                 let mut action_types = MultiValueEncoded::new();
                 action_types.push(
                     (
                         managed_address!(&mock_address),
                         managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+                        allowance,
+                        WhitelistLimits::default(),
+                        ManagedVec::new(),
                     )
                         .into(),
                 );
@@ -212,6 +237,64 @@ fn whitelist_actions_test() {
     ];
     setup.check_user_tokens_mock(&second_user_address, &expected_second_user_tokens);
 
+    // try exceed EGLD budget
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut args = ManagedVec::new();
+                args.push(ManagedBuffer::new_from_bytes(
+                    ManagedAddress::<DebugApi>::from_address(&second_user_address)
+                        .to_byte_array()
+                        .as_slice(),
+                ));
+                sc.take_action(
+                    managed_address!(&first_user_address),
+                    managed_address!(&mock_address),
+                    managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+                    args,
+                    OptionalValue::Some(EsdtTokenPayment::new(
+                        managed_token_id!(EGLD_TOKEN_ID),
+                        0,
+                        managed_biguint!(100),
+                    )),
+                );
+            },
+        )
+        .assert_user_error("Allowance exceeded");
+
+    // try exceed ESDT amount per call
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut args = ManagedVec::new();
+                args.push(ManagedBuffer::new_from_bytes(
+                    ManagedAddress::<DebugApi>::from_address(&second_user_address)
+                        .to_byte_array()
+                        .as_slice(),
+                ));
+                sc.take_action(
+                    managed_address!(&first_user_address),
+                    managed_address!(&mock_address),
+                    managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+                    args,
+                    OptionalValue::Some(EsdtTokenPayment::new(
+                        managed_token_id!(TOKEN_ID),
+                        0,
+                        managed_biguint!(250),
+                    )),
+                );
+            },
+        )
+        .assert_user_error("Amount exceeds per call limit");
+
     // remove whitelist
     setup
         .b_mock
@@ -263,3 +346,420 @@ fn whitelist_actions_test() {
         )
         .assert_user_error("Not whitelisted for action");
 }
+
+#[test]
+fn whitelist_expiry_and_rate_limit_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+    let mock_address = setup.mock_sc_wrapper.address_ref().clone();
+
+    // one call per 10 seconds, until timestamp 100
+    setup
+        .b_mock
+        .execute_tx(
+            &first_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut action_types = MultiValueEncoded::new();
+                action_types.push(
+                    (
+                        managed_address!(&mock_address),
+                        managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+                        ManagedVec::from_single_item(TokenAllowance {
+                            token_id: managed_token_id!(EGLD_TOKEN_ID),
+                            max_per_call: managed_biguint!(10),
+                            remaining_budget: managed_biguint!(100),
+                        }),
+                        WhitelistLimits {
+                            opt_expires_at: Some(100),
+                            opt_rate_limit: Some(RateLimit {
+                                max_calls: 1,
+                                period: 10,
+                            }),
+                        },
+                        ManagedVec::new(),
+                    )
+                        .into(),
+                );
+
+                sc.whitelist(managed_address!(&second_user_address), action_types);
+            },
+        )
+        .assert_ok();
+
+    let take_action = |setup: &mut AbstractionSetup<_>| {
+        setup.b_mock.execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut args = ManagedVec::new();
+                args.push(ManagedBuffer::new_from_bytes(
+                    ManagedAddress::<DebugApi>::from_address(&second_user_address)
+                        .to_byte_array()
+                        .as_slice(),
+                ));
+                sc.take_action(
+                    managed_address!(&first_user_address),
+                    managed_address!(&mock_address),
+                    managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+                    args,
+                    OptionalValue::Some(EsdtTokenPayment::new(
+                        managed_token_id!(EGLD_TOKEN_ID),
+                        0,
+                        managed_biguint!(10),
+                    )),
+                );
+            },
+        )
+    };
+
+    take_action(&mut setup).assert_ok();
+
+    // try exceed rate limit
+    take_action(&mut setup).assert_user_error("Rate limit exceeded");
+
+    // next period
+    setup.b_mock.set_block_timestamp(10);
+    take_action(&mut setup).assert_ok();
+
+    // expired entries are treated as absent
+    setup.b_mock.set_block_timestamp(100);
+    take_action(&mut setup).assert_user_error("Not whitelisted for action");
+
+    setup
+        .b_mock
+        .execute_query(&setup.sc_wrapper, |sc| {
+            assert!(sc
+                .get_all_whitelisted_users(managed_address!(&second_user_address))
+                .is_empty());
+        })
+        .assert_ok();
+
+    // anyone may prune expired entries
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                sc.prune_expired_whitelist(
+                    managed_address!(&first_user_address),
+                    managed_address!(&second_user_address),
+                );
+
+                let first_user_id = sc.user_ids().get_id(&managed_address!(&first_user_address));
+                let whitelist_id = sc
+                    .whitelist_ids()
+                    .get_id(&managed_address!(&second_user_address));
+                assert!(sc.user_whitelist(first_user_id, whitelist_id).is_empty());
+                assert!(!sc
+                    .all_users_for_whitelist(whitelist_id)
+                    .contains(&first_user_id));
+            },
+        )
+        .assert_ok();
+}
+
+#[test]
+fn take_action_prunes_expired_whitelist_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+    let mock_address = setup.mock_sc_wrapper.address_ref().clone();
+
+    // deposits never expire, withdrawals expire at timestamp 50
+    setup
+        .b_mock
+        .execute_tx(
+            &first_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut action_types = MultiValueEncoded::new();
+                action_types.push(
+                    (
+                        managed_address!(&mock_address),
+                        managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+                        ManagedVec::from_single_item(TokenAllowance {
+                            token_id: managed_token_id!(EGLD_TOKEN_ID),
+                            max_per_call: managed_biguint!(10),
+                            remaining_budget: managed_biguint!(100),
+                        }),
+                        WhitelistLimits::default(),
+                        ManagedVec::new(),
+                    )
+                        .into(),
+                );
+                action_types.push(
+                    (
+                        managed_address!(&mock_address),
+                        managed_buffer!(b"withdraw"),
+                        ManagedVec::new(),
+                        WhitelistLimits {
+                            opt_expires_at: Some(50),
+                            opt_rate_limit: None,
+                        },
+                        ManagedVec::new(),
+                    )
+                        .into(),
+                );
+
+                sc.whitelist(managed_address!(&second_user_address), action_types);
+            },
+        )
+        .assert_ok();
+
+    setup.b_mock.set_block_timestamp(50);
+
+    setup
+        .b_mock
+        .execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut args = ManagedVec::new();
+                args.push(ManagedBuffer::new_from_bytes(
+                    ManagedAddress::<DebugApi>::from_address(&second_user_address)
+                        .to_byte_array()
+                        .as_slice(),
+                ));
+                sc.take_action(
+                    managed_address!(&first_user_address),
+                    managed_address!(&mock_address),
+                    managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+                    args,
+                    OptionalValue::Some(EsdtTokenPayment::new(
+                        managed_token_id!(EGLD_TOKEN_ID),
+                        0,
+                        managed_biguint!(10),
+                    )),
+                );
+            },
+        )
+        .assert_ok();
+
+    // the expired entry was cleared by the take
+    setup
+        .b_mock
+        .execute_query(&setup.sc_wrapper, |sc| {
+            let first_user_id = sc.user_ids().get_id(&managed_address!(&first_user_address));
+            let whitelist_id = sc
+                .whitelist_ids()
+                .get_id(&managed_address!(&second_user_address));
+            let whitelist_mapper = sc.user_whitelist(first_user_id, whitelist_id);
+            assert_eq!(whitelist_mapper.len(), 1);
+
+            let expired_action_type = WhitelistAction::new(
+                managed_address!(&mock_address),
+                managed_buffer!(b"withdraw"),
+            );
+            assert!(!whitelist_mapper.contains(&expired_action_type));
+            assert!(sc
+                .whitelist_limits(first_user_id, whitelist_id, &expired_action_type)
+                .is_empty());
+        })
+        .assert_ok();
+}
+
+#[test]
+fn whitelist_arg_constraints_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+    let mock_address = setup.mock_sc_wrapper.address_ref().clone();
+
+    let whitelist = |setup: &mut AbstractionSetup<_>, constraint_type, values| {
+        setup.b_mock.execute_tx(
+            &first_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut action_types = MultiValueEncoded::new();
+                action_types.push(
+                    (
+                        managed_address!(&mock_address),
+                        managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+                        ManagedVec::from_single_item(TokenAllowance {
+                            token_id: managed_token_id!(EGLD_TOKEN_ID),
+                            max_per_call: managed_biguint!(100),
+                            remaining_budget: managed_biguint!(100),
+                        }),
+                        WhitelistLimits::default(),
+                        ManagedVec::from_single_item(ArgConstraint {
+                            arg_index: 0,
+                            constraint_type,
+                            values,
+                        }),
+                    )
+                        .into(),
+                );
+
+                sc.whitelist(managed_address!(&second_user_address), action_types);
+            },
+        )
+    };
+    let take_action = |setup: &mut AbstractionSetup<_>, deposit_address: &Address| {
+        setup.b_mock.execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut args = ManagedVec::new();
+                args.push(ManagedBuffer::new_from_bytes(
+                    ManagedAddress::<DebugApi>::from_address(deposit_address)
+                        .to_byte_array()
+                        .as_slice(),
+                ));
+                sc.take_action(
+                    managed_address!(&first_user_address),
+                    managed_address!(&mock_address),
+                    managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+                    args,
+                    OptionalValue::Some(EsdtTokenPayment::new(
+                        managed_token_id!(EGLD_TOKEN_ID),
+                        0,
+                        managed_biguint!(10),
+                    )),
+                );
+            },
+        )
+    };
+
+    // try whitelist with invalid constraint
+    whitelist(&mut setup, ArgConstraintType::Exact, ManagedVec::new())
+        .assert_user_error("Invalid argument constraint");
+
+    // deposits may only go back to the user
+    whitelist(
+        &mut setup,
+        ArgConstraintType::UserAddress,
+        ManagedVec::new(),
+    )
+    .assert_ok();
+
+    take_action(&mut setup, &second_user_address).assert_user_error("Argument constraint not met");
+    take_action(&mut setup, &first_user_address).assert_ok();
+}
+
+#[test]
+fn whitelist_allowance_restore_test() {
+    let mut setup = AbstractionSetup::new(account_abstraction::contract_obj);
+
+    let first_user_address = setup.first_user.clone();
+    let second_user_address = setup.second_user.clone();
+    let mock_address = setup.mock_sc_wrapper.address_ref().clone();
+
+    setup
+        .b_mock
+        .execute_tx(
+            &first_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut action_types = MultiValueEncoded::new();
+                action_types.push(
+                    (
+                        managed_address!(&mock_address),
+                        managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+                        ManagedVec::from_single_item(TokenAllowance {
+                            token_id: managed_token_id!(EGLD_TOKEN_ID),
+                            max_per_call: managed_biguint!(100),
+                            remaining_budget: managed_biguint!(100),
+                        }),
+                        WhitelistLimits::default(),
+                        ManagedVec::new(),
+                    )
+                        .into(),
+                );
+
+                sc.whitelist(managed_address!(&second_user_address), action_types);
+            },
+        )
+        .assert_ok();
+
+    // the first user is not registered in the mock, so the async call fails
+    let take_action = |setup: &mut AbstractionSetup<_>| {
+        setup.b_mock.execute_tx(
+            &second_user_address,
+            &setup.sc_wrapper,
+            &rust_biguint!(0),
+            |sc| {
+                let mut args = ManagedVec::new();
+                args.push(ManagedBuffer::new_from_bytes(
+                    ManagedAddress::<DebugApi>::from_address(&first_user_address)
+                        .to_byte_array()
+                        .as_slice(),
+                ));
+                sc.take_action(
+                    managed_address!(&first_user_address),
+                    managed_address!(&mock_address),
+                    managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+                    args,
+                    OptionalValue::Some(EsdtTokenPayment::new(
+                        managed_token_id!(EGLD_TOKEN_ID),
+                        0,
+                        managed_biguint!(100),
+                    )),
+                );
+            },
+        )
+    };
+
+    take_action(&mut setup).assert_ok();
+
+    setup
+        .b_mock
+        .execute_query(&setup.sc_wrapper, |sc| {
+            let allowance = sc.get_whitelist_allowance(
+                managed_address!(&first_user_address),
+                managed_address!(&second_user_address),
+                managed_address!(&mock_address),
+                managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+            );
+            assert_eq!(allowance.len(), 1);
+            assert_eq!(allowance.get(0).remaining_budget, managed_biguint!(100));
+        })
+        .assert_ok();
+
+    let expected_first_user_tokens = [
+        TxTokenTransfer {
+            token_identifier: EGLD_TOKEN_ID.to_vec(),
+            nonce: 0,
+            value: rust_biguint!(FIRST_USER_EGLD_BALANCE),
+        },
+        TxTokenTransfer {
+            token_identifier: TOKEN_ID.to_vec(),
+            nonce: 0,
+            value: rust_biguint!(FIRST_USER_ESDT_BALANCE),
+        },
+    ];
+    setup.check_user_tokens(&first_user_address, &expected_first_user_tokens);
+
+    // entries whitelisted before allowances existed have none stored
+    let owner_address = setup.owner.clone();
+    setup
+        .b_mock
+        .execute_tx(&owner_address, &setup.sc_wrapper, &rust_biguint!(0), |sc| {
+            let first_user_id = sc.user_ids().get_id(&managed_address!(&first_user_address));
+            let whitelist_id = sc
+                .whitelist_ids()
+                .get_id(&managed_address!(&second_user_address));
+            let action_type = WhitelistAction::new(
+                managed_address!(&mock_address),
+                managed_buffer!(DEPOSIT_TOKENS_ENDPOINT_NAME),
+            );
+            sc.whitelist_allowance(first_user_id, whitelist_id, &action_type)
+                .clear();
+        })
+        .assert_ok();
+
+    take_action(&mut setup).assert_user_error("Whitelist allowance not set");
+}
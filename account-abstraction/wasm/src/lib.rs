@@ -6,9 +6,9 @@
 
 // Init:                                 1
 // Upgrade:                              1
-// Endpoints:                           16
+// Endpoints:                           62
 // Async Callback:                       1
-// Total number of exported functions:  19
+// Total number of exported functions:  65
 
 #![no_std]
 
@@ -22,20 +22,66 @@ multiversx_sc_wasm_adapter::endpoints! {
         upgrade => upgrade
         registerUser => register_user
         depositForUser => deposit_for_user
+        withdraw => withdraw
+        withdrawForUser => withdraw_for_user
         getUserTokens => get_user_tokens
         getUserNonce => get_user_nonce
+        getChainId => chain_id
+        addUserKey => add_user_key
+        removeUserKey => remove_user_key
+        rotateUserKey => rotate_user_key
+        setSignatureThreshold => set_signature_threshold
+        getUserKeys => get_user_keys
+        getSignatureThreshold => get_signature_threshold
+        setGuardians => set_guardians
+        proposeRecovery => propose_recovery
+        approveRecovery => approve_recovery
+        cancelRecovery => cancel_recovery
+        executeRecovery => execute_recovery
+        getGuardians => get_guardians
+        getRecoveryConfig => get_recovery_config
+        getPendingRecovery => get_pending_recovery
+        authorizeSessionKey => authorize_session_key
+        revokeSessionKey => revoke_session_key
+        getSessionKeys => get_session_keys
+        setSpendingLimit => set_spending_limit
+        applySpendingLimitChange => apply_spending_limit_change
+        approveSpendingLimitChange => approve_spending_limit_change
+        getRemainingAllowance => get_remaining_allowance
+        getSpendingLimits => get_spending_limits
+        getPendingSpendingLimitChange => get_pending_spending_limit_change
+        invalidateNonces => invalidate_nonces
         multiActionForUser => multi_action_for_user
+        multiActionBatchForUser => multi_action_batch_for_user
+        atomicMultiActionBatchForUser => atomic_multi_action_batch_for_user
+        sessionMultiActionForUser => session_multi_action_for_user
         multiActionForMultiUsers => multi_action_for_multi_users
         whitelist => whitelist
         removeWhitelist => remove_whitelist
+        pruneExpiredWhitelist => prune_expired_whitelist
         takeAction => take_action
+        depositPaymasterBudget => deposit_paymaster_budget
+        withdrawPaymasterBudget => withdraw_paymaster_budget
+        setPaymasterPolicy => set_paymaster_policy
+        addSponsoredActions => add_sponsored_actions
+        removeSponsoredActions => remove_sponsored_actions
+        sponsoredMultiActionForUser => sponsored_multi_action_for_user
+        getPaymasterBudget => get_paymaster_budget
+        getPaymasterPolicy => get_paymaster_policy
+        getSponsoredActions => get_sponsored_actions
         saveIntents => save_intents
         multiUserSaveIntents => multi_user_save_intents
         executeIntent => execute_intent
+        cancelIntent => cancel_intent
+        cleanupExpiredIntents => cleanup_expired_intents
         getAllWhitelistedUsers => get_all_whitelisted_users
         getWhitelistTypes => get_whitelist_types
+        getWhitelistLimits => get_whitelist_limits
+        getWhitelistAllowance => get_whitelist_allowance
+        getWhitelistArgConstraints => get_whitelist_arg_constraints
         getAllUserIntentIds => get_all_user_intent_ids
         getIntentInfo => get_intent_info
+        getActionOutcomes => get_action_outcomes
     )
 }
 